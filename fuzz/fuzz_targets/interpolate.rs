@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use rust_shiori_lua::Interpolator;
+
+thread_local! {
+    static INTERPOLATOR: Interpolator = Interpolator::new().unwrap();
+}
+
+fuzz_target!(|source: &[u8]| {
+    let (processed, map) = INTERPOLATOR.with(|i| i.process_mapped(source)).expect("process_file raised an error");
+    // Only string literals are rewritten, so a file without any must come out unchanged.
+    if !source.iter().any(|c| b"'\"[".contains(c)) {
+        assert_eq!(processed, source);
+    }
+    // Every line must be mapped to a line of the source, since error messages about the processed script are read
+    // against the source.
+    let lines = |text: &[u8]| text.iter().filter(|&&c| c == b'\n').count() + 1;
+    assert_eq!(map.len(), lines(&processed), "process_file did not map every line");
+    assert!(map.iter().all(|p| p.line >= 1 && p.line <= lines(source)), "process_file mapped a line outside the source");
+});
@@ -0,0 +1,30 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use rust_shiori::{
+    request::{Request, FromRequestField, typed::{CloseReason, SwitchType}},
+    response::Response,
+};
+
+fn from_fields<'a, T: FromRequestField<'a>>(request: &'a Request) {
+    for value in request.fields().values() {
+        let _ = T::from_request_field(Some(value));
+    }
+}
+
+fuzz_target!(|text: &str| {
+    if let Ok(request) = Request::parse(text) {
+        assert_eq!(Request::parse(&request.to_wire()).as_ref(), Ok(&request));
+        let _ = request.as_typed();
+        let _ = request.passthru();
+        from_fields::<u32>(&request);
+        from_fields::<i64>(&request);
+        from_fields::<usize>(&request);
+        from_fields::<char>(&request);
+        from_fields::<CloseReason>(&request);
+        from_fields::<SwitchType>(&request);
+    }
+    if let Ok(response) = Response::parse(text) {
+        assert_eq!(Response::parse(&response.to_wire()).as_ref(), Ok(&response));
+    }
+});
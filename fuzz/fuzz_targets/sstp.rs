@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use rust_shiori::sstp::{Request, Response};
+
+fuzz_target!(|text: &str| {
+    if let Ok(request) = Request::parse(text) {
+        assert_eq!(Request::parse(&request.to_wire()).as_ref(), Ok(&request));
+    }
+    if let Ok(response) = Response::parse(text) {
+        assert_eq!(Response::parse(&response.to_wire()).as_ref(), Ok(&response));
+    }
+});
@@ -3,6 +3,10 @@ extern crate cc;
 use std::env;
 
 fn main() {
+    // cc asks to be rerun when its environment variables change, which would otherwise keep cargo from rerunning this
+    // when the Lua sources change.
+    println!("cargo:rerun-if-changed=lua");
+
     let target_os = env::var("CARGO_CFG_TARGET_OS");
     let target_family = env::var("CARGO_CFG_TARGET_FAMILY");
 
@@ -2712,9 +2712,12 @@ LUA_API int luaopen_eris(lua_State *L) {
   return 1;
 }
 
-// rust-shiori-lua: remove when rlua supports custom C libraries
-LUA_API int rsl_loaderis(lua_State *L) {
-  luaL_requiref(L, LUA_ERISLIBNAME, luaopen_eris, 1);
+/* luaopen_package reuses the preload table if it already exists, so this
+ * works even though it runs before any library is opened. */
+LUA_API void rsl_preloaderis(lua_State *L) {
+  luaL_getsubtable(L, LUA_REGISTRYINDEX, LUA_PRELOAD_TABLE);
+  lua_pushcfunction(L, luaopen_eris);
+  lua_setfield(L, -2, LUA_ERISLIBNAME);
   lua_pop(L, 1);
 }
 
@@ -149,8 +149,9 @@ LUA_API void eris_set_setting(lua_State *L, const char *name, int value);
  */
 LUA_API int luaopen_eris(lua_State* L);
 
-// rust-shiori-lua: remove when rlua supports custom C libraries
-LUA_API int rsl_loaderis(lua_State *L);
+/* rust-shiori-lua: makes require("eris") open the library. Called for every
+ * new state through luai_userstateopen (see luaconf.h). */
+LUA_API void rsl_preloaderis(lua_State *L);
 
 #endif
 
@@ -788,5 +788,14 @@
 #include "utf8_wrappers.h"
 #endif
 
+/*
+@@ luai_userstateopen is called at the end of lua_newstate. rust-shiori-lua
+** uses it to preload Eris (see rsl_preloaderis in eris.c), since rlua does
+** not give access to the lua_State to open C libraries any other way.
+*/
+struct lua_State;
+LUA_API void rsl_preloaderis (struct lua_State *L);
+#define luai_userstateopen(L)	rsl_preloaderis(L)
+
 #endif
 
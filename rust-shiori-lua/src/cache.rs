@@ -0,0 +1,130 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use log::{debug, warn};
+use rlua::{UserData, UserDataMethods};
+
+use crate::LUA_VERSION;
+
+/// Compiled scripts, saved as Lua bytecode so that loading them again skips the preprocessor and the compiler. Each
+/// script is cached in its own file, which starts with the key it was cached under: the script's path, size and
+/// modification time, and the version of the runtime. A cached script is only used if its key still matches. Exposed
+/// to the runtime's script searcher.
+///
+/// Writing an entry also removes the entries that can no longer be used: those cached by another version of the
+/// runtime, and those of scripts that no longer exist.
+pub struct ScriptCache {
+    dir: PathBuf,
+    version: String,
+}
+
+impl ScriptCache {
+    pub fn new(dir: PathBuf) -> Self {
+        ScriptCache { dir, version: Self::runtime_version() }
+    }
+
+    /// Changes whenever rust-shiori-lua, its Lua or its preprocessor do, since any of them can change the bytecode
+    /// a script compiles to.
+    fn runtime_version() -> String {
+        let preprocessor = [include_str!("lib/script/interpolate.lua"), include_str!("lib/script/dtags.lua")].concat();
+        let preprocessor = fnv1a(preprocessor.as_bytes());
+        let version = env!("CARGO_PKG_VERSION");
+        format!("rust-shiori-lua {}, Lua {}, preprocessor {:016x}", version, LUA_VERSION, preprocessor)
+    }
+
+    fn key(&self, script: &Path) -> io::Result<String> {
+        let metadata = fs::metadata(script)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(format!("{}\n{}\n{} {}\n", self.version, script.display(), metadata.len(), modified.as_nanos()))
+    }
+
+    fn entry(&self, script: &Path) -> PathBuf {
+        self.dir.join(format!("{:016x}.luac", fnv1a(script.as_os_str().as_encoded_bytes())))
+    }
+
+    /// The bytecode cached for `script`, if it is still up to date.
+    pub fn get(&self, script: &Path) -> Option<Vec<u8>> {
+        let key = self.key(script).ok()?;
+        let data = fs::read(self.entry(script)).ok()?;
+        data.strip_prefix(key.as_bytes()).map(<[u8]>::to_vec)
+    }
+
+    pub fn put(&self, script: &Path, bytecode: &[u8]) -> io::Result<()> {
+        let key = self.key(script)?;
+        fs::create_dir_all(&self.dir)?;
+        let entry = self.entry(script);
+        let mut temp_name = entry.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+        temp_name.push(".tmp");
+        let temp = entry.with_file_name(temp_name);
+
+        let mut file = File::create(&temp)?;
+        file.write_all(key.as_bytes())?;
+        file.write_all(bytecode)?;
+        drop(file);
+        fs::rename(&temp, &entry)?;
+        self.remove_stale(&entry);
+        Ok(())
+    }
+
+    /// Removes every entry other than `current` that was cached by another version of the runtime, or whose script
+    /// no longer exists.
+    fn remove_stale(&self, current: &Path) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for path in entries.filter_map(|e| Some(e.ok()?.path())) {
+            if path == current || path.extension().is_none_or(|e| e != "luac") {
+                continue;
+            }
+            let mut lines = match File::open(&path) {
+                Ok(file) => BufReader::new(file).lines(),
+                Err(_) => continue,
+            };
+            let version = lines.next().and_then(Result::ok);
+            let script = lines.next().and_then(Result::ok);
+            let stale = version.as_deref() != Some(self.version.as_str())
+                || script.is_none_or(|script| !Path::new(&script).is_file());
+            if stale {
+                match fs::remove_file(&path) {
+                    Ok(()) => debug!("Removed the stale cache entry {}.", path.display()),
+                    Err(e) => warn!("Could not remove the stale cache entry {}: {}", path.display(), e),
+                }
+            }
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash of `bytes`. Unlike `DefaultHasher`, it hashes the same in every build, so that entries
+/// outlive upgrades of Rust.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+impl UserData for ScriptCache {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Returns the cached bytecode of the script at `path`, or nil if there is none or it is out of date.
+        methods.add_method("get", |ctx, this, path: rlua::String| {
+            let path = String::from_utf8_lossy(path.as_bytes());
+            match this.get(Path::new(&*path)) {
+                Some(bytecode) => {
+                    debug!("Loaded the compiled script {} from the cache.", path);
+                    ctx.create_string(&bytecode).map(Some)
+                },
+                None => Ok(None),
+            }
+        });
+
+        // Caches `bytecode` for the script at `path`. Failing to is only worth a warning, since the script still runs.
+        methods.add_method("put", |_, this, (path, bytecode): (rlua::String, rlua::String)| {
+            let path = String::from_utf8_lossy(path.as_bytes());
+            match this.put(Path::new(&*path), bytecode.as_bytes()) {
+                Ok(()) => debug!("Cached the compiled script {}.", path),
+                Err(e) => warn!("Could not cache the compiled script {}: {}", path, e),
+            }
+            Ok(())
+        });
+    }
+}
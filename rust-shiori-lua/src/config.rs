@@ -1,35 +1,285 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
-use config::{Config as RawConfig, File, FileFormat};
-use serde::Deserialize;
+use config::{Config as RawConfig, Environment, File, FileFormat, Source};
+use rlua::{Context, Table, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::persistent;
 
 pub use config::ConfigError;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    pub ghost: Ghost,
     pub lua: Lua,
+    pub security: Security,
     pub logging: Logging,
+    #[serde(default)]
+    pub script: toml::value::Table, // Free-form settings for the ghost's scripts.
+    #[serde(skip)]
+    pub ignored_env: Vec<String>, // Keys of environment variables that aren't configuration keys, to warn about.
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ghost {
+    pub name: Option<String>, // Used in the log. Defaults to the name of the ghost directory.
+    pub sender: String, // The `Sender` of responses to GET requests.
+    pub charset: String, // The `Charset` of responses. Only UTF-8 is supported for now.
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Lua {
     pub init: String,
     pub script_path: Vec<PathBuf>,
     pub library_path: Vec<PathBuf>,
-    pub persistent: String, // TODO: Make this a path
+    pub persistent: PathBuf, // Relative to the ghost directory.
+    pub persistent_backups: usize,
+    pub persistent_format: persistent::Format,
+    pub autosave: u64, // Seconds between saves of persistent data, or 0 to only save on exit.
+    pub persist_suspended: bool, // If set, scripts waiting for events are saved on exit and resumed next time.
+    pub suspended: PathBuf, // Where suspended scripts are saved, relative to the ghost directory.
+    pub reload_on_change: bool, // If set, scripts are reloaded when one of them changes.
+    pub cache_scripts: bool, // If set, compiled scripts are cached.
+    pub script_cache: PathBuf, // Where compiled scripts are cached, relative to the ghost directory.
+    pub debug: bool, // If set, script errors in GET requests are shown in a balloon instead of only being logged.
+    pub sandbox: Sandbox,
+    pub limits: Limits,
+}
+
+/// Limits on each request, where 0 means no limit.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub time: u64, // Milliseconds.
+    pub instructions: u64,
+    pub memory: usize, // Bytes, for the whole Lua state.
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sandbox {
+    pub enabled: bool,
+    pub modules: Vec<String>, // Standard library modules visible to scripts.
+    pub native_libraries: bool, // Whether `package.loadlib` and `package.cpath` may be used.
+    pub data: PathBuf, // Where scripts may write files, relative to the ghost directory.
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Security {
+    pub external_events: Vec<String>, // Events that requests with `SecurityLevel: external` may trigger.
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Logging {
     pub level: log::LevelFilter,
+    #[serde(default)]
+    pub targets: BTreeMap<String, log::LevelFilter>, // Levels of log targets and those under them, e.g. `lua`.
     pub path: PathBuf,
+    pub format: LogFormat,
+    pub max_size: u64, // Bytes the log may grow to before it is rotated, or 0 for no limit.
+    pub rotate_daily: bool,
+    pub keep: usize, // How many rotated logs are kept, or 0 to overwrite the log every session.
+    pub sink: Option<Sink>, // Where records are also sent, e.g. a live log viewer.
+    pub record: Option<PathBuf>, // If set, raw SHIORI traffic is recorded here. See `rust_shiori::record`.
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text, // Lines like `12:34:56 [INFO] [#12 OnBoot] message`.
+    Json, // One JSON object per line.
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Sink {
+    Stderr,
+    Udp { address: SocketAddr },
 }
 
+/// The ghost's configuration, relative to the ghost directory.
+pub const GHOST_CONFIG: &str = "rust-shiori.toml";
+
+/// Values set by scripts through `shiori.set_config`, relative to the ghost directory.
+pub const SCRIPT_CONFIG: &str = "profile/script-config.toml";
+
+/// The user's overrides of the ghost's configuration, relative to the ghost directory.
+pub const USER_CONFIG: &str = "profile/rust-shiori.toml";
+
+/// Environment variables named `RUST_SHIORI_<SECTION>__<KEY>` override everything else.
+const ENV_PREFIX: &str = "RUST_SHIORI";
+const ENV_SEPARATOR: &str = "__";
+
 impl Config {
-    pub fn try_load(path: &Path) -> Result<Self, ConfigError> {
-        let mut config = RawConfig::new();
-        config.merge(File::from_str(include_str!("default.toml"), FileFormat::Toml))?;
-        config.merge(File::from(path).required(false))?;
-        config.try_into()
+    /// Loads the configuration of the ghost in `ghost_path`. Each layer overrides the ones before it: the defaults,
+    /// the ghost's configuration, values set by scripts, the user's configuration and the environment.
+    pub fn try_load(ghost_path: &Path) -> Result<Self, ConfigError> {
+        Self::from_layers(ghost_path, None)
+    }
+
+    /// Loads the configuration, with `script_config` in place of the contents of the file of values set by scripts.
+    fn from_layers(ghost_path: &Path, script_config: Option<&str>) -> Result<Self, ConfigError> {
+        let mut raw = RawConfig::new();
+        raw.merge(File::from_str(include_str!("default.toml"), FileFormat::Toml))?;
+        raw.merge(File::from(ghost_path.join(GHOST_CONFIG)).format(FileFormat::Toml).required(false))?;
+        match script_config {
+            Some(text) => raw.merge(File::from_str(text, FileFormat::Toml))?,
+            None => raw.merge(File::from(ghost_path.join(SCRIPT_CONFIG)).format(FileFormat::Toml).required(false))?,
+        };
+        raw.merge(File::from(ghost_path.join(USER_CONFIG)).format(FileFormat::Toml).required(false))?;
+        let ignored_env = Self::merge_env(&mut raw)?;
+
+        let mut config: Config = serde_path_to_error::deserialize(raw).map_err(|e| {
+            ConfigError::Message(format!("Invalid value for `{}`: {}", e.path(), e.inner()))
+        })?;
+        config.script = Self::script_section(ghost_path, script_config)?;
+        config.ignored_env = ignored_env;
+        config.validate()?;
+        Ok(config)
     }
-}
\ No newline at end of file
+
+    /// Merges the environment variables into `raw`, except for those that don't name a configuration key, whose keys
+    /// are returned instead. Unlike unknown keys in the files, these are ignored, since the environment is shared
+    /// with everything else the user runs, such as other ghosts and other versions of rust-shiori-lua.
+    fn merge_env(raw: &mut RawConfig) -> Result<Vec<String>, ConfigError> {
+        let mut env: Vec<_> = Environment::with_prefix(ENV_PREFIX).separator(ENV_SEPARATOR).collect()?
+            .into_iter().collect();
+        env.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut ignored = Vec::new();
+        for (key, value) in env {
+            let mut with_key = raw.clone();
+            let unknown = match with_key.set(&key, value.clone()) {
+                Ok(_) => matches!(with_key.try_into::<Config>(),
+                    Err(ConfigError::Message(m)) if m.starts_with("unknown field")),
+                Err(_) => true,
+            };
+            if unknown {
+                ignored.push(key);
+            } else {
+                raw.set(&key, value)?;
+            }
+        }
+        Ok(ignored)
+    }
+
+    /// Merges the `[script]` sections of the layers, which `config` would lowercase the keys of. The names of
+    /// environment variables have no case, so they override the keys that match them ignoring case.
+    fn script_section(ghost_path: &Path, script_config: Option<&str>) -> Result<toml::value::Table, ConfigError> {
+        let mut script = toml::value::Table::new();
+        for name in &[GHOST_CONFIG, SCRIPT_CONFIG, USER_CONFIG] {
+            let path = ghost_path.join(name);
+            let text = match script_config {
+                Some(text) if *name == SCRIPT_CONFIG => text.to_string(),
+                _ => match fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                },
+            };
+            let mut layer = text.parse::<toml::Value>()
+                .map_err(|e| ConfigError::Message(format!("{}: {}", path.display(), e)))?;
+            if let Some(toml::Value::Table(section)) = layer.as_table_mut().and_then(|t| t.remove("script")) {
+                merge_tables(&mut script, section, false);
+            }
+        }
+
+        let mut env = RawConfig::new();
+        env.merge(Environment::with_prefix(ENV_PREFIX).separator(ENV_SEPARATOR))?;
+        if let Ok(section) = env.get::<toml::value::Table>("script") {
+            merge_tables(&mut script, section, true);
+        }
+        Ok(script)
+    }
+
+    /// Checks what the types of the values can't express.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, message: &str| {
+            Err(ConfigError::Message(format!("Invalid value for `{}`: {}", key, message)))
+        };
+        if !self.ghost.charset.eq_ignore_ascii_case("UTF-8") {
+            return invalid("ghost.charset", "only UTF-8 is supported")
+        }
+        let header_values = [("ghost.name", self.ghost.name.as_deref().unwrap_or("")), ("ghost.sender", &self.ghost.sender)];
+        for (key, value) in &header_values {
+            if value.contains(['\r', '\n']) {
+                return invalid(key, "must not contain line breaks")
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the table scripts see as `config`, with the sections `ghost`, `lua`, `logging` and `script`.
+    pub fn to_lua<'lua>(&self, ctx: Context<'lua>) -> rlua::Result<Table<'lua>> {
+        fn section<'lua, T: Serialize>(ctx: Context<'lua>, section: &T) -> rlua::Result<Value<'lua>> {
+            let value = toml::Value::try_from(section).map_err(|e| rlua::Error::ToLuaConversionError {
+                from: "Config",
+                to: "table",
+                message: Some(e.to_string()),
+            })?;
+            persistent::toml_to_lua(ctx, value)
+        }
+
+        ctx.create_table_from(vec![
+            ("ghost", section(ctx, &self.ghost)?),
+            ("lua", section(ctx, &self.lua)?),
+            ("logging", section(ctx, &self.logging)?),
+            ("script", section(ctx, &self.script)?),
+        ])
+    }
+
+    /// Sets `key` (e.g. `lua.autosave`) to `value` in the values set by scripts, which take effect the next time the
+    /// SHIORI is loaded. Fails without changing anything if the resulting configuration would be invalid.
+    pub fn set_script_value(ghost_path: &Path, key: &str, value: toml::Value) -> Result<(), ConfigError> {
+        let path = ghost_path.join(SCRIPT_CONFIG);
+        let mut values = match fs::read_to_string(&path) {
+            Ok(text) => text.parse::<toml::Value>()
+                .map_err(|e| ConfigError::Message(format!("{}: {}", path.display(), e)))?,
+            Err(_) => toml::Value::Table(Default::default()),
+        };
+
+        let mut table = &mut values;
+        let mut parts = key.split('.').peekable();
+        while let Some(part) = parts.next() {
+            let entries = match table {
+                toml::Value::Table(entries) => entries,
+                _ => return Err(ConfigError::Message(format!("Invalid key `{}`", key))),
+            };
+            if parts.peek().is_none() {
+                entries.insert(part.to_string(), value);
+                break
+            }
+            table = entries.entry(part.to_string()).or_insert_with(|| toml::Value::Table(Default::default()));
+        }
+
+        let text = toml::to_string(&values)
+            .map_err(|e| ConfigError::Message(format!("Invalid value for `{}`: {}", key, e)))?;
+        Self::from_layers(ghost_path, Some(&text))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+        }
+        fs::write(&path, text).map_err(|e| ConfigError::Foreign(Box::new(e)))
+    }
+}
+
+/// Merges `from` into `into`, replacing values other than tables that both have. Replaced keys keep their name in
+/// `into`.
+fn merge_tables(into: &mut toml::value::Table, from: toml::value::Table, ignore_case: bool) {
+    for (key, value) in from {
+        let existing = into.keys().find(|k| if ignore_case { k.eq_ignore_ascii_case(&key) } else { **k == key }).cloned();
+        match (existing.and_then(|k| into.remove_entry(&k)), value) {
+            (Some((k, toml::Value::Table(mut table))), toml::Value::Table(value)) => {
+                merge_tables(&mut table, value, ignore_case);
+                into.insert(k, toml::Value::Table(table));
+            },
+            (existing, value) => { into.insert(existing.map_or(key, |(k, _)| k), value); },
+        }
+    }
+}
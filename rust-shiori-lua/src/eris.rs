@@ -1,22 +1,31 @@
-use std::os::raw::c_void;
-use std::marker::PhantomData;
-use std::cell::UnsafeCell;
+use rlua::{Context, Function, Table, Value};
 
-use rlua::Lua;
+// Our build of Lua preloads Eris into every state it creates (see `rsl_preloaderis` in eris.c), so it is opened
+// through `require` like any other module, rather than by reaching into rlua for the `lua_State`.
 
-extern "C" {
-    pub fn rsl_loaderis(lua: *mut c_void);
+fn eris_function<'lua>(ctx: Context<'lua>, name: &str) -> rlua::Result<Function<'lua>> {
+    let require: Function = ctx.globals().get("require")?;
+    let eris: Table = require.call("eris")?;
+    eris.get(name)
 }
 
-struct LuaInternals {
-    main_state: *mut c_void,
-    _no_ref_unwind_safe: PhantomData<UnsafeCell<()>>
+/// Serializes `value` with Eris. Values that are keys of `permanents` are saved as the corresponding value instead.
+pub fn persist<'lua>(ctx: Context<'lua>, permanents: Option<Table<'lua>>, value: Value<'lua>)
+    -> rlua::Result<rlua::String<'lua>>
+{
+    let permanents = match permanents {
+        Some(p) => p,
+        None => ctx.create_table()?,
+    };
+    eris_function(ctx, "persist")?.call((permanents, value))
 }
 
-pub fn load_eris<'a>(lua: &'a mut Lua) {
-    // this may technically be UB (Lua isn't #[repr(C)]) but it's temporary and works
-    unsafe {
-        let lua: &'a mut LuaInternals = std::mem::transmute(lua);
-        rsl_loaderis(lua.main_state);
-    }
-}
\ No newline at end of file
+/// Deserializes data saved by `persist`. `permanents` must map the values that were used for permanent values back to
+/// them.
+pub fn unpersist<'lua>(ctx: Context<'lua>, permanents: Option<Table<'lua>>, data: &[u8]) -> rlua::Result<Value<'lua>> {
+    let permanents = match permanents {
+        Some(p) => p,
+        None => ctx.create_table()?,
+    };
+    eris_function(ctx, "unpersist")?.call((permanents, ctx.create_string(data)?))
+}
@@ -38,12 +38,20 @@ impl fmt::Display for LoadError {
             LoadError::ConfigError(e) => ("A configuration", format!("{}", e)),
             LoadError::IOError(e) => ("An IO", format!("{}", e)),
             LoadError::LogError(e) => ("A logging", format!("{}", e)),
-            LoadError::LuaError(e) => ("A lua", format!("{}", e)),
+            LoadError::LuaError(e) => ("A lua", lua_error_details(e)),
         };
         write!(f, "{} error occured while loading the SHIORI. Details:\n{}", ty, message)
     }
 }
 
+/// Formats a Lua error, including the cause of errors raised by Rust callbacks, which `Display` leaves out.
+pub(crate) fn lua_error_details(error: &rlua::Error) -> String {
+    match error {
+        rlua::Error::CallbackError { traceback, cause } => format!("{}\n{}", cause, traceback),
+        e => e.to_string(),
+    }
+}
+
 pub enum UnloadError {
     IOError(std::io::Error),
     LuaError(rlua::Error),
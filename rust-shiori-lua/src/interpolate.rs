@@ -0,0 +1,56 @@
+use include_lua::*;
+use rlua::{Lua, Function, RegistryKey, Table};
+
+/// Loads just enough of the runtime to call `script.interpolate`'s `process_file`.
+const LOAD_INTERPOLATE: &str = r#"
+    local searcher = ...
+    local loaded = {}
+    function rsl_require(module)
+        if loaded[module] == nil then loaded[module] = searcher(module)(module) end
+        return loaded[module]
+    end
+    return rsl_require("script.interpolate").process_file
+"#;
+
+/// Where a line of a preprocessed script starts in its source: a line, and a column in characters if it starts partway
+/// through that line, as the code after an interpolated string does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+/// Runs the string interpolation preprocessor outside of a running SHIORI, exactly as the script searcher does
+/// before loading a script. Exists for tooling and fuzzing.
+pub struct Interpolator {
+    lua: Lua,
+    process_file: RegistryKey,
+}
+
+impl Interpolator {
+    pub fn new() -> rlua::Result<Self> {
+        let lua = Lua::new();
+        let process_file = lua.context(|ctx| {
+            let searcher = ctx.make_searcher(include_lua!("[shiori libs]": "lib"))?;
+            let process_file: Function = ctx.load(LOAD_INTERPOLATE).set_name("interpolate loader")?.call(searcher)?;
+            ctx.create_registry_value(process_file)
+        })?;
+        Ok(Interpolator { lua, process_file })
+    }
+
+    pub fn process(&self, source: &[u8]) -> rlua::Result<Vec<u8>> {
+        self.process_mapped(source).map(|(script, _)| script)
+    }
+
+    /// Preprocesses `source`, also returning where each line of the result starts in it.
+    pub fn process_mapped(&self, source: &[u8]) -> rlua::Result<(Vec<u8>, Vec<SourcePosition>)> {
+        self.lua.context(|ctx| {
+            let process_file: Function = ctx.registry_value(&self.process_file)?;
+            let (script, map): (rlua::String, Vec<Table>) = process_file.call(ctx.create_string(source)?)?;
+            let map = map.into_iter()
+                .map(|position| Ok(SourcePosition { line: position.get("line")?, column: position.get("column")? }))
+                .collect::<rlua::Result<_>>()?;
+            Ok((script.as_bytes().to_vec(), map))
+        })
+    }
+}
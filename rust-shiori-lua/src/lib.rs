@@ -1,7 +1,5 @@
 use std::ffi::OsString;
-use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::ops::Deref;
 
 #[cfg(windows)]
 use self::os_str::OsStringExt; // Implements `OsString::into_vec` on Windows.
@@ -10,25 +8,37 @@ use std::os::unix::ffi::OsStringExt;
 
 use include_lua::*;
 
-use log::{info, debug, error, Level, Record};
+use log::{info, debug, warn, error, Level, Record};
 
 use rust_shiori::{
     shiori, Shiori,
-    request::{Request, Method},
+    context::RequestContext,
+    request::{Request, Method, FieldValue},
     response::{Response, ResponseStatus, ResponseBuilder}
 };
 
 use rlua::{Lua, Table, Function, Context};
 
-use simplelog::{WriteLogger, Config as LogConfig};
-
+#[cfg(windows)]
 mod os_str;
+mod cache;
 mod config;
 mod error;
 mod eris;
+mod interpolate;
+mod limits;
+mod logging;
+mod persistent;
+mod sandbox;
+mod watch;
 
+use self::cache::ScriptCache;
 use self::config::Config;
-use self::error::*;
+use self::limits::Budget;
+use self::persistent::{Format, PersistentFile};
+use self::watch::{ScriptWatcher, WatchedScripts};
+pub use self::error::*;
+pub use self::interpolate::{Interpolator, SourcePosition};
 
 const LUA_VERSION: &str = "5.3";
 
@@ -40,37 +50,66 @@ pub struct LuaShiori {
     config: config::Config,
     lua: Lua,
     responder: rlua::RegistryKey,
+    budget: Budget,
+    watcher: Option<ScriptWatcher>,
 }
 
-struct ShioriInit<'a> {
+struct ShioriInit<'a, 'lua> {
     init: &'a str,
     searcher: Searcher,
-    persistent_path: &'a str,
+    persistent: PersistentFile,
+    suspended: Option<PersistentFile>,
+    script_cache: Option<ScriptCache>,
+    watched_scripts: Option<WatchedScripts>,
+    sandbox: Option<Table<'lua>>,
+    external_events: &'a [String],
+    autosave: u64,
+    config: Table<'lua>,
 }
 
-impl<'lua, 'a> rlua::ToLuaMulti<'lua> for ShioriInit<'a> {
-    fn to_lua_multi(self, lua: Context<'lua>) -> rlua::Result<rlua::MultiValue> {
-        rlua::ToLuaMulti::to_lua_multi((self.init, self.searcher, self.persistent_path), lua)
+impl<'lua, 'a> rlua::ToLuaMulti<'lua> for ShioriInit<'a, 'lua> {
+    fn to_lua_multi(self, lua: Context<'lua>) -> rlua::Result<rlua::MultiValue<'lua>> {
+        let options = lua.create_table()?;
+        options.set("init", self.init)?;
+        options.set("persistent", self.persistent)?;
+        options.set("suspended", self.suspended)?;
+        options.set("script_cache", self.script_cache)?;
+        options.set("watched_scripts", self.watched_scripts)?;
+        options.set("sandbox", self.sandbox)?;
+        options.set("external_events", self.external_events.to_vec())?;
+        options.set("autosave", self.autosave)?;
+        options.set("config", self.config)?;
+        rlua::ToLuaMulti::to_lua_multi((self.searcher, options), lua)
     }
 }
 
+/// What the responder returned for a request.
+struct ScriptResponse {
+    text: Option<String>,
+    code: u32,
+    headers: Vec<(String, String)>,
+}
+
 impl LuaShiori {
     fn load(path: PathBuf) -> Result<Self, LoadError> {
-        let config = Config::try_load(&path.join("rust-shiori.toml"))?;
+        let config = Config::try_load(&path)?;
 
-        if config.logging.level != log::LevelFilter::Off {
-            let log_file = File::create(&path.join(&config.logging.path))?;
-            let log_config = LogConfig { target: Some(log::Level::Trace), .. LogConfig::default() };
-            WriteLogger::init(config.logging.level, log_config, log_file)?;
-            debug!("Logging successfully initialized.");
+        logging::init(&path, &config.logging)?;
+        debug!("Logging successfully initialized.");
+        for key in &config.ignored_env {
+            warn!("Ignoring the environment variable for `{}`, which is not a configuration key.", key);
         }
 
-        let mut lua = unsafe { Lua::new_with_debug() }; // Debug is used for error reporting.
-        eris::load_eris(&mut lua);
+        let lua = unsafe { Lua::new_with_debug() }; // Debug is used for error reporting.
+        let budget = Budget::install(&lua, &config.lua.limits);
+        let watched_scripts = WatchedScripts::default();
         let responder = lua.context(|ctx| -> Result<_, LoadError> {
             Self::create_lua_logger(&ctx)?;
             debug!("Lua logging interface loaded.");
 
+            budget.create_lua_interface(&ctx)?;
+            Self::create_config_interface(&ctx, &path, config.lua.sandbox.enabled)?;
+
             let searcher = ctx.make_searcher(include_lua!("[shiori libs]": "lib"))?;
             debug!("Lua libraries loaded.");
 
@@ -80,29 +119,76 @@ impl LuaShiori {
             Self::set_lua_paths(&ctx, &path, &config)?;
             debug!("Lua search paths set.");
 
+            let sandbox = if config.lua.sandbox.enabled {
+                if !config.lua.sandbox.native_libraries {
+                    sandbox::disable_native_libraries(ctx)?;
+                }
+                let files = sandbox::FileAccess::new(&path, &config)?;
+                let sandbox = sandbox::create_sandbox(ctx, &files, &config.lua.sandbox)?;
+                debug!("Lua sandbox created.");
+                Some(sandbox)
+            } else {
+                None
+            };
+
             let init_params = ShioriInit { 
                 init: &config.lua.init, 
-                searcher: searcher, 
-                persistent_path: &config.lua.persistent,
+                searcher, 
+                persistent: PersistentFile::new(
+                    path.join(&config.lua.persistent), config.lua.persistent_backups, config.lua.persistent_format),
+                suspended: Some(path.join(&config.lua.suspended)).filter(|_| config.lua.persist_suspended)
+                    .map(|path| PersistentFile::new(path, 0, Format::Eris)),
+                // Sandboxed scripts can't replace cached scripts, since `FileAccess` keeps them out of the cache.
+                script_cache: Some(path.join(&config.lua.script_cache)).filter(|_| config.lua.cache_scripts)
+                    .map(ScriptCache::new),
+                watched_scripts: Some(watched_scripts.clone()).filter(|_| config.lua.reload_on_change),
+                sandbox,
+                external_events: &config.security.external_events,
+                autosave: config.lua.autosave,
+                config: config.to_lua(ctx)?,
             };
 
-            let responder = responder_ctor.call::<_, Function>(init_params)?;
+            budget.start("Loading the scripts");
+            let responder = responder_ctor.call::<_, Function>(init_params);
+            budget.stop();
+            let responder = responder?;
             let responder_key = ctx.create_registry_value(responder)?;
             debug!("Responder created.");
 
             Ok(responder_key)
         })?;
 
-        info!("SHIORI load complete.");
+        let watcher = if config.lua.reload_on_change {
+            Some(ScriptWatcher::new(config.lua.script_path.iter().map(|p| path.join(p)).collect(), watched_scripts))
+        } else {
+            None
+        };
+
+        let name = config.ghost.name.clone()
+            .or_else(|| path.canonicalize().ok()?.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        info!("SHIORI load complete for {}.", name);
 
         Ok(LuaShiori {
-            path: path,
-            config: config,
-            lua: lua,
-            responder: responder,
+            path,
+            config,
+            lua,
+            responder,
+            budget,
+            watcher,
         })
     }
 
+    /// Replaces the clock that timers and autosaves go by, which is the system clock by default, with `clock`, which
+    /// returns the time in seconds since the Unix epoch. Exists for `shiori-runner`, whose ticks come faster than once a
+    /// second. The clock timers go by never goes back, so `clock` shouldn't be behind the one it replaces.
+    pub fn set_clock(&mut self, clock: impl Fn() -> i64 + Send + 'static) {
+        let result = self.lua.context(|ctx| ctx.globals().set("_clock", ctx.create_function(move |_, ()| Ok(clock()))?));
+        if let Err(e) = result {
+            error!("Could not set the clock: {}", e);
+        }
+    }
+
     fn set_lua_paths(ctx: &Context, ghost_path: &Path, config: &Config) -> rlua::Result<()> {
         let separator = OsString::from(";");
         let package = ctx.globals().get::<_, Table>("package")?;
@@ -129,17 +215,106 @@ impl LuaShiori {
         Ok(())
     }
 
+    /// Creates the table that scripts receive for `request`. It contains every raw field of the request, the named
+    /// fields of its typed request if it is of a known type (e.g. `shell` for OnBoot), and the standard fields as
+    /// properties: `method`, `id`, `sender`, `charset`, `security_level` and `status`.
+    fn create_event_table<'lua>(ctx: &Context<'lua>, request: &Request) -> rlua::Result<Table<'lua>> {
+        let event = ctx.create_table_from(request.fields().iter().map(|(k, v)| (k.as_str(), v.as_str())))?;
+        let typed = request.as_typed();
+        event.set("method", typed.method().as_str())?;
+        event.set("id", typed.id())?;
+        event.set("sender", typed.sender())?;
+        event.set("charset", typed.charset())?;
+        event.set("security_level", typed.security_level())?;
+        event.set("status", typed.status())?;
+        for (name, value) in typed.kind().field_values() {
+            match value {
+                FieldValue::Missing => (),
+                FieldValue::Text(t) => event.set(name, t)?,
+                FieldValue::Integer(i) => event.set(name, i)?,
+                FieldValue::Char(c) => event.set(name, c.to_string())?,
+            }
+        }
+        Ok(event)
+    }
+
     fn create_lua_logger(ctx: &Context) -> rlua::Result<()> {
         ctx.globals().set("_log", ctx.create_function(
             |_, (level, text, file, line): (String, String, Option<String>, Option<u32>)| {
                 let level = level.parse().unwrap_or(Level::Debug);
                 let mut record = Record::builder();
-                record.level(level).file(file.as_ref().map(Deref::deref)).line(line);
+                record.level(level).target("lua").file(file.as_deref()).line(line);
                 log::logger().log(&record.args(format_args!("{}", text)).build());
                 Ok(())
             }
         )?)
     }
+
+    /// Logs a script error that occured while responding to `id`. In debug mode, a GET request is answered with a
+    /// balloon describing the error instead, so the ghost's developer notices it.
+    fn script_error(&self, response: ResponseBuilder, request: &Request, id: &str, details: &str) -> ResponseBuilder {
+        error!("A script error occured while responding to {}. Details: {}", id, details);
+        if !self.config.lua.debug || request.method() != Method::Get {
+            return response
+        }
+        response.with_status(ResponseStatus::OK)
+            .with_field("Sender", &self.config.ghost.sender)
+            .with_field("Value", &error_balloon(id, details))
+    }
+
+    /// Creates `_set_config(key, value)`, which `shiori.set_config` uses to change the configuration for the next load.
+    /// It returns whether it succeeded and the error message. Sandboxed scripts may only change `[ghost]` and `[script]`,
+    /// since the other sections decide what the sandbox allows.
+    fn create_config_interface(ctx: &Context, ghost_path: &Path, sandboxed: bool) -> rlua::Result<()> {
+        let ghost_path = ghost_path.to_path_buf();
+        ctx.globals().set("_set_config", ctx.create_function(move |_, (key, value): (String, rlua::Value)| {
+            if sandboxed && !(key.starts_with("ghost.") || key.starts_with("script.")) {
+                return Ok((false, Some(format!("The sandbox does not allow setting `{}`", key))));
+            }
+            let value = match Self::lua_to_toml(value) {
+                Some(v) => v,
+                None => return Ok((false, Some(format!("Invalid value for `{}`", key)))),
+            };
+            match Config::set_script_value(&ghost_path, &key, value) {
+                Ok(()) => Ok((true, None)),
+                Err(e) => Ok((false, Some(e.to_string()))),
+            }
+        })?)
+    }
+
+    /// Converts booleans, numbers, strings and sequences of them. Returns `None` for anything else.
+    fn lua_to_toml(value: rlua::Value) -> Option<toml::Value> {
+        match value {
+            rlua::Value::Boolean(b) => Some(toml::Value::Boolean(b)),
+            rlua::Value::Integer(i) => Some(toml::Value::Integer(i)),
+            rlua::Value::Number(n) => Some(toml::Value::Float(n)),
+            rlua::Value::String(s) => s.to_str().ok().map(|s| toml::Value::String(s.to_string())),
+            rlua::Value::Table(t) => t.sequence_values().map(|v| v.ok().and_then(Self::lua_to_toml))
+                .collect::<Option<_>>().map(toml::Value::Array),
+            _ => None,
+        }
+    }
+}
+
+/// How much of each line of an error message an error balloon shows, in characters.
+const ERROR_BALLOON_LENGTH: usize = 200;
+
+/// SakuraScript showing the summary at the start of `details`: where the error occured and what it was, then the
+/// handler it occured in, if the runtime could tell.
+fn error_balloon(id: &str, details: &str) -> String {
+    let escape = |text: &str| text.replace('\\', "\\\\").replace('%', "\\%");
+    let summary: Vec<String> = details.lines()
+        .take_while(|line| !line.is_empty() && !line.starts_with("stack traceback:"))
+        .take(2)
+        .map(|line| {
+            let mut message: String = line.chars().take(ERROR_BALLOON_LENGTH).collect();
+            if message.len() < line.len() {
+                message.push_str("...");
+            }
+            escape(&message)
+        })
+        .collect();
+    format!("\\0\\b2\\_qScript error in {}\\n\\n[half]{}\\e", escape(id), summary.join("\\n"))
 }
 
 impl Shiori for LuaShiori {
@@ -152,33 +327,58 @@ impl Shiori for LuaShiori {
     }
 
     fn respond(&mut self, request: Request) -> Response {
-        let mut response = ResponseBuilder::new().with_field("Charset", "UTF-8");
+        if self.watcher.as_mut().is_some_and(ScriptWatcher::changed) {
+            info!("A script has changed, reloading.");
+            let reload = Request::new(Method::Notify).with_field("ID", "OnShioriReload");
+            let _context = RequestContext::enter(Some("OnShioriReload"));
+            self.respond(reload);
+        }
+
+        let mut response = ResponseBuilder::new().with_field("Charset", &self.config.ghost.charset);
+        let id = request.get_field("ID").unwrap_or("(no ID)");
 
-        let respond_raw = |ctx: Context| -> rlua::Result<(Option<String>, u32)> {
-            let field_table = ctx.create_table_from(request.fields().into_iter().map(|(k, v)| (k.as_str(), v.as_str())))?;
-            let response = ctx.registry_value::<Function>(&self.responder)?.call::<_, Table>((field_table, request.method().as_str()))?;
-            Ok((response.get("text")?, response.get("code")?))
+        let respond_raw = |ctx: Context| -> rlua::Result<ScriptResponse> {
+            let event = Self::create_event_table(&ctx, &request)?;
+            let response = ctx.registry_value::<Function>(&self.responder)?.call::<_, Table>((event, request.method().as_str()))?;
+            let headers = match response.get::<_, Option<Table>>("headers")? {
+                Some(h) => h.pairs().collect::<rlua::Result<_>>()?,
+                None => Vec::new(),
+            };
+            Ok(ScriptResponse { text: response.get("text")?, code: response.get("code")?, headers })
         };
 
-        match self.lua.context(respond_raw) {
-            Ok((r, c)) => {
-                let status = ResponseStatus::from_code(c).unwrap_or(ResponseStatus::InternalServerError);
+        self.budget.start(id);
+        let result = self.lua.context(respond_raw);
+        let exceeded = self.budget.exceeded().is_some();
+        self.budget.stop();
+
+        match result {
+            Ok(ScriptResponse { text: r, code: c, headers }) => {
+                let status = ResponseStatus::from_code(c).unwrap_or_else(|_| {
+                    error!("A script responded to {} with the invalid status code {}.", id, c);
+                    ResponseStatus::InternalServerError
+                });
                 response = response.with_status(status);
                 if !status.is_error() {
-                    if request.method() == Method::Get {
-                        response = response.with_field("Sender", "rust-shiori-lua");
-                        if let Some(r) = r {
+                    if matches!(request.method(), Method::Get | Method::Teach) {
+                        response = response.with_field("Sender", &self.config.ghost.sender);
+                        if let Some(r) = r.filter(|_| status != ResponseStatus::NoContent) {
                             response = response.with_field("Value", &r);
                         }
                     }
+                    for (name, value) in &headers {
+                        response = response.with_field(name, value);
+                    }
                 }
                 else {
-                    match r {
-                        Some(e) => error!("A script error occured while responding to a request. Details: {}", e),
-                        None => error!("A script error occured while responding to a request. No details available."),
-                    }
+                    let details = r.unwrap_or_else(|| "No details available.".to_string());
+                    response = self.script_error(response, &request, id, &details);
                 }
             },
+            Err(e) if exceeded || matches!(e, rlua::Error::MemoryError(_)) => {
+                response = response.with_status(ResponseStatus::InternalServerError);
+                response = self.script_error(response, &request, id, &lua_error_details(&e));
+            },
             Err(e) => {
                 error!("An internal error occured while responding to a request. This is a bug! Details:\n{}", e);
                 response = response.with_status(ResponseStatus::InternalServerError);
@@ -187,5 +387,9 @@ impl Shiori for LuaShiori {
         
         response.build().unwrap()
     }
+
+    fn record_path(&self) -> Option<PathBuf> {
+        self.config.logging.record.clone()
+    }
 }
 
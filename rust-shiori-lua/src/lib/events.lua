@@ -1,30 +1,107 @@
+local utils = rsl_require("utils")
+
 local events = {
     event_handlers = {},
+    -- Handlers for requests with `SecurityLevel: external`, which never reach the handlers above unless configured to.
+    external_event_handlers = {},
     event_preprocessors = {},
+    -- Scripts waiting in resume_on_event(s), as a set of `{ routine = <coroutine>, events = {[event] = filter} }`.
+    suspended = {},
 }
 
+-- Handlers registered by ghost scripts (as opposed to the runtime or a suspended script), which are replaced
+-- when the scripts are reloaded.
+local script_handlers = setmetatable({}, {__mode = "k"})
+
 function events.push_event_handler(event, func)
     if not events.event_handlers[event] then events.event_handlers[event] = {} end
     table.insert(events.event_handlers[event], 1, func)
 end
 
 function events.push_static_event_handler(event, func)
-    events.push_event_handler(event, function(_)
+    local handler = function(_)
         -- strip the event ID from arguments
         local expand_params = function(_, params) return func(table.unpack(params)) end
         return coroutine.create(expand_params), false 
+    end
+    events.push_event_handler(event, handler)
+    return handler
+end
+
+function events.push_script_event_handler(event, func)
+    script_handlers[events.push_static_event_handler(event, func)] = true
+end
+
+function events.push_external_event_handler(event, func)
+    if not events.external_event_handlers[event] then events.external_event_handlers[event] = {} end
+    table.insert(events.external_event_handlers[event], 1, function(_)
+        local expand_params = function(_, params) return func(table.unpack(params)) end
+        return coroutine.create(expand_params), false
     end)
 end
 
+-- Removes every handler and preprocessor registered by ghost scripts, and returns the tables from before the
+-- removal so they can be restored if reloading fails.
+function events.remove_script_handlers()
+    local saved = { handlers = {}, preprocessors = {} }
+    for event, handlers in pairs(events.event_handlers) do
+        local kept = {}
+        for _, handler in ipairs(handlers) do
+            if not script_handlers[handler] then kept[#kept + 1] = handler end
+        end
+        saved.handlers[event] = handlers
+        events.event_handlers[event] = kept
+    end
+    saved.preprocessors, saved.external_handlers = events.event_preprocessors, events.external_event_handlers
+    events.event_preprocessors, events.external_event_handlers = {}, {}
+    return saved
+end
+
+function events.restore_handlers(saved)
+    events.event_handlers = saved.handlers
+    events.event_preprocessors = saved.preprocessors
+    events.external_event_handlers = saved.external_handlers
+end
+
+-- Moves the handlers that were kept by remove_script_handlers in front of any registered since, so that
+-- suspended scripts still take priority over static handlers.
+function events.prioritize_kept_handlers(saved)
+    for event, handlers in pairs(events.event_handlers) do
+        local old = saved.handlers[event] or {}
+        local kept, new = {}, {}
+        for _, handler in ipairs(handlers) do
+            if utils.contains(old, handler) then kept[#kept + 1] = handler else new[#new + 1] = handler end
+        end
+        utils.extend(kept, new)
+        events.event_handlers[event] = kept
+    end
+end
+
 function events.resume_on_event(event, filter)
     if filter == nil then filter = function(...) return true end end
     local _, params = events.resume_on_events({[event] = filter})
     return table.unpack(params)
 end
 
+-- Registers handlers that resume a suspended script on the first of its events that passes the filter.
+local function push_resume_handlers(wait)
+    for event, filter in pairs(wait.events) do
+        events.push_event_handler(event,
+            function(e)
+                -- Ensure the coroutine will only be resumed once.
+                if not events.suspended[wait] then return nil, true end
+                if filter(table.unpack(e)) then
+                    events.suspended[wait] = nil
+                    return wait.routine, true
+                end
+                return nil, false
+            end
+        )
+    end
+end
+
 function events.resume_on_events(event_table)
-    local again = true -- Ensure the coroutine will only be resumed once. 
-    local routine = coroutine.running()
+    local wait = { routine = coroutine.running(), events = {} }
     for event, filter in pairs(event_table) do
 
         -- If event_table has string array elements, use them as events to resume on unconditionally.
@@ -32,21 +109,21 @@ function events.resume_on_events(event_table)
             event = filter
             filter = function(...) return true end
         end
-
-        events.push_event_handler(event,
-            function(e)
-                if again and filter(table.unpack(e)) then
-                    again = false
-                    return routine, true
-                else
-                    return nil, not again 
-                end 
-            end
-        )
+        wait.events[event] = filter
     end
+    events.suspended[wait] = true
+    push_resume_handlers(wait)
     return coroutine.yield()
 end
 
+-- Suspends the scripts in `waits`, a list of the keys of `events.suspended` from a previous session.
+function events.restore_suspended(waits)
+    for _, wait in ipairs(waits) do
+        events.suspended[wait] = true
+        push_resume_handlers(wait)
+    end
+end
+
 function events.set_event_preprocessor(event, preprocessor)
     events.event_preprocessors[event] = preprocessor
 end
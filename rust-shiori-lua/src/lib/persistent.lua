@@ -1,39 +1,152 @@
 local logger = rsl_require("logger")
-local events = rsl_require("events")
-local eris = require("eris")
+local utils = rsl_require("utils")
+
+-- Whether `value` is data saved along with its schema version, rather than data saved before versioning.
+local function is_versioned(value)
+    if type(value) ~= "table" or math.type(value.schema_version) ~= "integer" or type(value.data) ~= "table" then
+        return false
+    end
+    for key in pairs(value) do
+        if key ~= "schema_version" and key ~= "data" then return false end
+    end
+    return true
+end
 
 return {
-    Persistent = function(persistent_path)
+    -- `file` is provided by rust-shiori-lua, which handles writing atomically and keeping backups.
+    Persistent = function(file, autosave_interval)
         local persistent = {
-            save_path = persistent_path,
+            file = file,
             data = {},
+            saved = nil, -- The data as it was last saved or loaded, to avoid rewriting it if unchanged.
+            last_autosave = utils.now(),
+            -- The schema version of the data, or nil if there was no save, in which case the data is assumed to be
+            -- up to date once the scripts have registered their migrations.
+            version = nil,
+            migrations = {}, -- Maps each version to the migration from it registered by the current scripts.
+            failed = false, -- Set when a migration fails, to keep the save file as it was.
         }
+
+        -- Lets scripts call `persistent.save()` and `persistent.migrate()` without storing the functions in the data.
+        local data_meta = { __index = {
+            save = function() return persistent.save() end,
+            migrate = function(from, to, fn) return persistent.migrate(from, to, fn) end,
+        } }
+
+        -- Calls `fn` with the data stripped of its metatable.
+        local function without_meta(fn, ...)
+            setmetatable(persistent.data, nil)
+            local result = table.pack(pcall(fn, ...))
+            setmetatable(persistent.data, data_meta)
+            if not result[1] then error(result[2], 0) end
+            return table.unpack(result, 2, result.n)
+        end
+
+        local function serialize()
+            local saved = { schema_version = persistent.version or 0, data = persistent.data }
+            return without_meta(persistent.file.serialize, persistent.file, saved)
+        end
+
+        -- Replaces the contents of the data with those of `new`, since scripts hold on to the data table itself.
+        local function replace_data(new)
+            local data = persistent.data
+            for key in pairs(data) do rawset(data, key, nil) end
+            for key, value in pairs(new) do rawset(data, key, value) end
+        end
     
         function persistent.load()
-            local file, data, err, code
-            file, err, code = io.open(persistent.save_path, "rb")
-            if file then 
-                data, err, code = file:read("*all")
-                file:close()
+            local saved, err = persistent.file:load()
+            if is_versioned(saved) then
+                persistent.data, persistent.version = saved.data, saved.schema_version
+            elseif saved then
+                persistent.data, persistent.version = saved, 0
+            elseif err then
+                logger.warn("Failed to load persistent data: %s", err)
             end
-            if data then 
-                persistent.data = eris.unpersist(data) 
-            else
-                logger.warn("Failed to load persistent data: %s %s", err, code)
+            setmetatable(persistent.data, data_meta)
+            local ok, saved = pcall(serialize)
+            persistent.saved = ok and saved or nil
+        end
+
+        -- Runs every registered migration that applies to the data, in order. If one fails, the data is restored to
+        -- how it was loaded, and saving is disabled so the save file is kept for a fixed migration to work on.
+        local function run_migrations()
+            local version = persistent.version
+            if not version or persistent.failed or not persistent.migrations[version] then return end
+            local original_version = persistent.version
+            local original = without_meta(utils.deep_copy, persistent.data)
+            while persistent.migrations[persistent.version] do
+                local from, migration = persistent.version, persistent.migrations[persistent.version]
+                local ok, result = xpcall(without_meta, debug.traceback, migration.fn, persistent.data)
+                if not ok then
+                    replace_data(original)
+                    persistent.version, persistent.failed = original_version, true
+                    logger.error("Failed to migrate persistent data from version %d to %d, so it will not be saved "
+                        .. "until the ghost is restarted. %s", from, migration.to, result)
+                    return
+                end
+                if type(result) == "table" and result ~= persistent.data then replace_data(result) end
+                logger.info("Migrated persistent data from version %d to %d.", from, migration.to)
+                persistent.version = migration.to
+            end
+        end
+
+        -- Registers `fn` to migrate the data from schema version `from` to `to`. `fn` is passed the data, and may
+        -- either change it or return a new table to replace it. Migrations run as soon as the data's version allows.
+        function persistent.migrate(from, to, fn)
+            if math.type(from) ~= "integer" or math.type(to) ~= "integer" or from < 0 or to <= from then
+                error("schema versions must be integers with 0 <= from < to", 2)
+            end
+            if type(fn) ~= "function" then error("migration must be a function", 2) end
+            if persistent.migrations[from] then
+                error(("a migration from version %d is already registered"):format(from), 2)
+            end
+            persistent.migrations[from] = { to = to, fn = fn }
+            run_migrations()
+        end
+
+        -- Called once the scripts have been (re)loaded. Sets the version of new data to the latest one that the
+        -- scripts migrate to, warns about data that couldn't be brought up to date, and forgets the migrations so
+        -- reloaded scripts can register them again.
+        function persistent.finish_migrations()
+            local latest = 0
+            for _, migration in pairs(persistent.migrations) do latest = math.max(latest, migration.to) end
+            if not persistent.version then
+                persistent.version = latest
+            elseif persistent.version > latest and latest > 0 then
+                logger.warn("Persistent data has schema version %d, which is newer than the scripts know about (%d).",
+                    persistent.version, latest)
+            elseif persistent.version < latest and not persistent.failed then
+                logger.warn("Persistent data has schema version %d, but there is no migration from it to version %d.",
+                    persistent.version, latest)
             end
+            persistent.migrations = {}
         end
     
+        -- Saves the data unless it is unchanged since it was last saved or loaded. Returns true, or false and an error
+        -- message. Raises an error if the data can't be serialized.
         function persistent.save()
-            local file, err, code = io.open(persistent.save_path, "wb")
-            if file then
-                _, err, code = file:write(eris.persist(persistent.data))
-                file:close()
-            end
-            if err then
-                logger.warn("Failed to save persistent data: %s %s", err, code)
+            if persistent.failed then return false, "saving is disabled because a migration failed" end
+            local data = serialize()
+            if data == persistent.saved then return true end
+            local ok, err = persistent.file:save(data)
+            if ok then
+                persistent.saved = data
+            else
+                logger.warn("Failed to save persistent data: %s", err)
             end
+            return ok, err
+        end
+
+        -- Saves the data if at least `autosave_interval` seconds have passed since the last autosave.
+        function persistent.autosave()
+            if autosave_interval <= 0 then return end
+            if os.difftime(utils.now(), persistent.last_autosave) < autosave_interval then return end
+            persistent.last_autosave = utils.now()
+            local ok, err = pcall(persistent.save)
+            if not ok then logger.warn("Failed to autosave persistent data: %s", err) end
         end
     
         return persistent
     end
-}
\ No newline at end of file
+}
@@ -0,0 +1,93 @@
+-- Maps positions in preprocessed scripts, as Lua reports them, back to the files the scripts were loaded from. The
+-- preprocessor puts each interpolated expression on a line of its own, so a position in a script can be mapped to a
+-- line and, for the lines it made, a column of the file.
+
+local positions = {}
+
+local traceback = debug.traceback
+
+-- The map of each script loaded from a file, keyed by its `short_src`, as made by `script.interpolate`. Scripts loaded
+-- from the cache are mapped by a function that makes the map when it is first needed.
+local maps = {}
+
+-- Registers `map` for the script loaded with the chunk name `chunkname`.
+function positions.add(chunkname, map)
+    maps[debug.getinfo(load("", chunkname), "S").short_src] = map
+end
+
+local function get_map(src)
+    local map = maps[src]
+    if type(map) == "function" then
+        local ok, result = pcall(map)
+        map = ok and result or {}
+        maps[src] = map
+    end
+    return map
+end
+
+-- The line, and column if known, in the file of the script `src` (a `short_src`) that `line` of the script is at.
+function positions.locate(src, line)
+    local map = get_map(src)
+    local position = map and map[line]
+    if not position then return line end
+    return position.line, position.column
+end
+
+-- The script, line and end of the position formatted as `short_src:line` at `pos` in `text`, if the script is mapped.
+local function position_at(text, pos)
+    for src in pairs(maps) do
+        if text:sub(pos, pos + #src) == src .. ":" then
+            local line, after = text:match("^(%d+)()", pos + #src + 1)
+            if line then return src, tonumber(line), after end
+        end
+    end
+end
+
+-- Rewrites the positions in mapped scripts that Lua puts in `text`, an error message or a traceback: those at its
+-- start, as in `init.lua:12: attempt to call a nil value`, those starting the lines of a traceback, and those of the
+-- functions it names, as in `in function <init.lua:10>`. Lines are mapped, and columns are added where known, except
+-- to where functions are defined. Anything other than a string is returned as it is.
+function positions.rewrite(text)
+    if type(text) ~= "string" then return text end
+    local parts, pos = {}, 1
+    local function rewrite_at(start)
+        if start < pos then return end
+        local src, line, after = position_at(text, start)
+        local delimiter = src and text:sub(after, after)
+        if delimiter ~= ":" and delimiter ~= ">" then return end
+        local line, column = positions.locate(src, line)
+        parts[#parts + 1] = text:sub(pos, start - 1)
+        parts[#parts + 1] = (delimiter == ":" and column) and ("%s:%d:%d"):format(src, line, column)
+            or ("%s:%d"):format(src, line)
+        pos = after
+        return after
+    end
+
+    -- An error raised again with `error` starts with where it was raised each time.
+    local start = 1
+    while true do
+        local after = rewrite_at(start)
+        if not after or text:sub(after, after + 1) ~= ": " then break end
+        start = after + 2
+    end
+    local starts = {}
+    for start in text:gmatch("\n\t()") do starts[#starts + 1] = start end
+    for start in text:gmatch("<()") do starts[#starts + 1] = start end
+    table.sort(starts)
+    for _, start in ipairs(starts) do rewrite_at(start) end
+
+    parts[#parts + 1] = text:sub(pos)
+    return table.concat(parts)
+end
+
+-- Replaces `debug.traceback`, with the positions in the traceback rewritten.
+function positions.traceback(...)
+    local thread, message, level
+    if type((...)) == "thread" then thread, message, level = ... else message, level = ... end
+    -- Levels in the running thread count from here, so skip this function.
+    if not thread or thread == coroutine.running() then level = (level or 1) + 1 end
+    if thread then return positions.rewrite(traceback(thread, message, level)) end
+    return positions.rewrite(traceback(message, level))
+end
+
+return positions
@@ -0,0 +1,66 @@
+local utils = rsl_require("utils")
+local positions = rsl_require("positions")
+
+-- Lines of source shown before and after the line an error occured on.
+local CONTEXT_LINES = 2
+
+local report = {}
+
+-- The innermost and outermost frames of `thread` that run code loaded from a file, i.e. scripts rather than the
+-- runtime, whose chunks have other names. The innermost is where the error occured, the outermost is usually the
+-- handler that was called.
+local function script_frames(thread)
+    local innermost, outermost
+    local level = 0
+    while true do
+        local info = debug.getinfo(thread, level, "Sl")
+        if not info then break end
+        if info.source:sub(1, 1) == "@" and info.currentline > 0 then
+            innermost = innermost or info
+            outermost = info
+        end
+        level = level + 1
+    end
+    return innermost, outermost
+end
+
+-- The lines around `line` in the file `path`, with `line` marked, or nil if the file can't be read.
+local function snippet(path, line)
+    local file = io.open(path, "r")
+    if not file then return nil end
+    local lines, n = {}, 0
+    for text in file:lines() do
+        n = n + 1
+        if n > line + CONTEXT_LINES then break end
+        if n >= line - CONTEXT_LINES then
+            lines[#lines + 1] = ("%s %4d | %s"):format(n == line and ">" or " ", n, text)
+        end
+    end
+    file:close()
+    return #lines > 0 and table.concat(lines, "\n") or nil
+end
+
+-- Describes the error `e` (a message, or a table with a `message`) raised in `thread` while handling `event`: the
+-- message, the handler that failed, the source lines where the error occured, and a traceback. If `thread` is nil,
+-- the error is being handled in the thread calling this, by a message handler of `xpcall` calling this directly.
+function report.build(e, thread, event)
+    -- The script has failed, so stop its budget: if it was exceeded, the hook would otherwise fire again in here.
+    _budget_stop()
+    if not utils.istable(e) or not e.message then e = { message = e } end
+    local innermost, outermost = script_frames(thread or coroutine.running())
+
+    local parts = { utils.tostring_or_nil(positions.rewrite(e.message)) }
+    if event and outermost then
+        parts[#parts + 1] = ("in the handler for %s defined at %s:%d"):format(
+            event, outermost.short_src, (positions.locate(outermost.short_src, outermost.linedefined)))
+    elseif event then
+        parts[#parts + 1] = ("while handling %s"):format(event)
+    end
+    local source = innermost
+        and snippet(innermost.source:sub(2), (positions.locate(innermost.short_src, innermost.currentline)))
+    if source then parts[#parts + 1] = "\n" .. source .. "\n" end
+    parts[#parts + 1] = thread and debug.traceback(thread) or debug.traceback(nil, 2)
+    return table.concat(parts, "\n")
+end
+
+return report
@@ -0,0 +1,123 @@
+local utils = rsl_require("utils")
+
+local scheduler = {
+    -- The current time, as returned by `utils.now`. See `advance`.
+    now = utils.now(),
+    -- Set of timers: `{ due = <time>, routine = <coroutine> }` for scripts waiting in `wait`, or `{ due = <time>,
+    -- fn = <function>, interval = <seconds or nil>, daily = <{hour, min, sec} or nil> }` for callbacks.
+    timers = {},
+}
+
+local timer_meta = { __index = {} }
+
+-- Stops the timer. Does nothing if it already ran and doesn't repeat.
+function timer_meta.__index.cancel(timer) scheduler.timers[timer] = nil end
+
+-- The first time after `after` that the clock shows `daily`.
+local function next_daily(daily, after)
+    local t = os.date("*t", after)
+    t.hour, t.min, t.sec, t.isdst = daily[1], daily[2], daily[3], nil
+    local due = os.time(t)
+    if due <= after then
+        t.day = t.day + 1
+        due = os.time(t)
+    end
+    return due
+end
+
+local function check_seconds(name, seconds)
+    if type(seconds) ~= "number" or seconds < 0 then
+        error(("bad argument #1 to '%s' (non-negative number expected, got %s)"):format(name, tostring(seconds)), 3)
+    end
+end
+
+local function check_function(name, fn)
+    if type(fn) ~= "function" then
+        error(("bad argument #2 to '%s' (function expected, got %s)"):format(name, type(fn)), 3)
+    end
+end
+
+local function add(timer)
+    scheduler.timers[timer] = true
+    return setmetatable(timer, timer_meta)
+end
+
+-- Moves the clock to the time of the current request. The clock never goes back.
+function scheduler.advance()
+    scheduler.now = math.max(scheduler.now, utils.now())
+end
+
+-- Suspends the running script for `seconds`.
+function scheduler.wait(seconds)
+    check_seconds("wait", seconds)
+    if not coroutine.isyieldable() then error("wait can only be called from an event handler", 2) end
+    add{ due = scheduler.now + seconds, routine = coroutine.running() }
+    coroutine.yield()
+end
+
+function scheduler.after(seconds, fn)
+    check_seconds("after", seconds)
+    check_function("after", fn)
+    return add{ due = scheduler.now + seconds, fn = fn }
+end
+
+function scheduler.every(seconds, fn)
+    check_seconds("every", seconds)
+    check_function("every", fn)
+    if seconds == 0 then error("bad argument #1 to 'every' (interval must be positive)", 2) end
+    return add{ due = scheduler.now + seconds, fn = fn, interval = seconds }
+end
+
+-- `time` is either a time as returned by `os.time`, to run `fn` once, or a local time of day as "HH:MM" or
+-- "HH:MM:SS", to run it every day at that time.
+function scheduler.at(time, fn)
+    check_function("at", fn)
+    if type(time) == "number" then return add{ due = time, fn = fn } end
+
+    local hour, min, sec = tostring(time):match("^(%d%d?):(%d%d)$")
+    if not hour then hour, min, sec = tostring(time):match("^(%d%d?):(%d%d):(%d%d)$") end
+    local daily = { tonumber(hour), tonumber(min), tonumber(sec) or 0 }
+    if not hour or daily[1] > 23 or daily[2] > 59 or daily[3] > 59 then
+        error(("bad argument #1 to 'at' (time or \"HH:MM[:SS]\" expected, got %s)"):format(tostring(time)), 2)
+    end
+    return add{ due = next_daily(daily, scheduler.now), fn = fn, daily = daily }
+end
+
+-- Takes the timer that has been due the longest, rescheduling it if it repeats, and returns a coroutine that runs
+-- it. Returns nil if no timer is due.
+function scheduler.take_due()
+    local earliest
+    for timer in pairs(scheduler.timers) do
+        if timer.due <= scheduler.now and (earliest == nil or timer.due < earliest.due) then earliest = timer end
+    end
+    if not earliest then return nil end
+
+    if earliest.interval then
+        local missed = math.floor((scheduler.now - earliest.due) / earliest.interval)
+        earliest.due = earliest.due + (missed + 1) * earliest.interval
+    elseif earliest.daily then
+        earliest.due = next_daily(earliest.daily, scheduler.now)
+    else
+        scheduler.timers[earliest] = nil
+    end
+    if earliest.routine then return earliest.routine end
+    local fn = earliest.fn
+    return coroutine.create(function() fn() end)
+end
+
+-- Removes the callbacks scheduled by the scripts, keeping scripts waiting in `wait`, and returns the timers from
+-- before the removal so they can be restored if reloading fails.
+function scheduler.remove_callbacks()
+    local saved = scheduler.timers
+    scheduler.timers = {}
+    for timer in pairs(saved) do
+        if timer.routine then scheduler.timers[timer] = true end
+    end
+    return saved
+end
+
+function scheduler.restore_callbacks(saved)
+    scheduler.timers = saved
+end
+
+return scheduler
@@ -14,32 +14,115 @@ local function prepare_string(str, open, close)
     return str, open, close
 end
 
+-- Markers are left in strings as they are expanded to carry positions in the file through the expansion. Each is
+-- a kind and an offset in the file between two runs of NUL bytes, longer than any run in the file itself so that
+-- they can't be confused with its contents. An "e" marker is put before each closing brace in a string, with the
+-- brace's offset, and a "b" marker is a line break to put in the script, with the offset of what follows it.
+local function Markers(file)
+    local longest = 0
+    for run in file:gmatch("\0+") do longest = math.max(longest, #run) end
+    local fence = ("\0"):rep(longest + 1)
+    local markers = {}
+
+    -- The start of every line of the file, to find the line and column of an offset.
+    local line_starts = { 1 }
+    for pos in file:gmatch("\n()") do line_starts[#line_starts + 1] = pos end
+
+    -- Tags nested in others can be expanded twice, and their offsets thrown off, so offsets are kept in the file.
+    function markers.mark(kind, offset) return fence .. kind .. math.max(1, offset) .. fence end
+
+    -- A pattern matching markers of the given kinds.
+    function markers.pattern(kinds) return fence .. "[" .. kinds .. "]%d+" .. fence end
+
+    function markers.strip(str, kinds) return (str:gsub(markers.pattern(kinds or "eb"), "")) end
+
+    -- The offset of the last closing brace marked in `str`.
+    function markers.last_brace(str)
+        local offset
+        for o in str:gmatch(fence .. "e(%d+)" .. fence) do offset = tonumber(o) end
+        return offset
+    end
+
+    -- The line and column of `offset` in the file. Columns count UTF-8 characters, where the line is valid UTF-8.
+    local function position(offset)
+        offset = math.max(1, math.min(offset, #file + 1))
+        local low, high = 1, #line_starts
+        while low < high do
+            local mid = (low + high + 1) // 2
+            if line_starts[mid] <= offset then low = mid else high = mid - 1 end
+        end
+        local start = line_starts[low]
+        return low, (utf8.len(file, start, offset - 1) or offset - start) + 1
+    end
+
+    -- Turns the "b" markers in `script` into line breaks, and maps each line of the result to where it starts in the
+    -- file: a line, and a column for lines that start partway through one. Every other line break in `script` is one
+    -- of the file's, in the same order.
+    function markers.resolve(script)
+        local out, map, line = {}, { { line = 1 } }, 1
+        local pos = 1
+        local function copy(text)
+            for _ in text:gmatch("\n") do
+                line = line + 1
+                map[#map + 1] = { line = line }
+            end
+            out[#out + 1] = text
+        end
+        for before, offset, after in script:gmatch("()" .. fence .. "b(%d+)" .. fence .. "()") do
+            copy(script:sub(pos, before - 1))
+            local column
+            line, column = position(tonumber(offset))
+            map[#map + 1] = { line = line, column = column }
+            out[#out + 1] = "\n"
+            pos = after
+        end
+        copy(script:sub(pos))
+        return table.concat(out), map
+    end
+
+    return markers
+end
+
 -- Because multiline strings ignore the first character after their open brace when it is a newline,
--- splitting a multiline string can delete line breaks. This function duplicates all line breaks that
--- follow such an open brace, except for the very first, which was present in the original and really
--- should be ignored.
-local function fix_newlines(str, open)
+-- splitting a multiline string can delete line breaks. This function puts a "\n" literal in front of
+-- every open brace that follows an expanded tag and is followed by a line break. Only the open braces
+-- put there by the expansion are changed, which are always just after the "b" marker that ends a tag;
+-- the same characters in the string's contents are left alone.
+local function fix_newlines(str, open, markers)
     local open_pat = open:gsub("%[", "%%[")
-    return str:gsub(open_pat .. "\n", open .. "\n\n"):gsub("^" .. open_pat .. "\n\n", open .. "\n")
+    return (str:gsub("(" .. markers.pattern("b") .. ")(" .. open_pat .. "\n)", '%1"\\n" .. %2'))
 end
 
-local function expand_string(str, open, close, escapes)
+-- Expands the tags in the string `str`, which starts at `offset` in the file. Each tag's expression is put on a line
+-- of its own, between "b" markers, so that errors in it can be told apart from errors in the code around it. Lua
+-- reports errors in an expression, and in concatenating it, on the line of the operator after it, so the operators
+-- around it are on its line too.
+local function expand_string(str, offset, open, close, escapes, markers)
+    local original_open = open
     local str, open, close = prepare_string(str, open, close)
+    -- Only the delimiters can have grown, so the rest of the string is this far from where it is in the file.
+    local shift = #open - #original_open
+    str = str:gsub("()}", function(pos) return markers.mark("e", offset + pos - 1 - shift) .. "}" end)
     for prefix, tags in pairs(dtags.TAGS) do
         local pattern = ("(\\*)(%s)(%%b{})"):format(prefix)
         str = str:gsub(pattern, function(slashes, pfull, pkeep, body)
             if (not escapes) or (slashes .. pfull):match("\\*"):len() % 2 == 0 then 
+                local inner = markers.strip(body:sub(2, #body - 1))
                 for tag, expr in pairs(tags) do
-                    local tag_match = {body:sub(2, #body - 1):match(tag)}
-                    if tag_match[1] ~= nil then return ("%s%s%s .. %s .. %s"):format(
-                        slashes, pkeep, close, expr:format(table.unpack(tag_match)), open
-                    ) end
+                    local tag_match = {inner:match(tag)}
+                    if tag_match[1] ~= nil then
+                        local tag_end = (markers.last_brace(body) or offset) + 1
+                        local tag_start = tag_end - #inner - 2 - (#pfull - #pkeep)
+                        return ("%s%s%s%s .. %s .. %s%s"):format(slashes, pkeep, close, markers.mark("b", tag_start),
+                            expr:format(table.unpack(tag_match)), markers.mark("b", tag_end), open)
+                    end
                 end
             end
             return ("%s%s{%s}"):format(slashes, pfull, body)
         end)
     end
-    return ("(%s)"):format(fix_newlines(str, open))
+    str = markers.strip(str, "e")
+    return ("(%s)"):format(fix_newlines(str, open, markers))
 end
 
 local string_types = {
@@ -68,7 +151,13 @@ local function find_close(file, os, oe, stype)
     end
 end
 
+-- Expands the interpolated strings in `file`. Returns the resulting script, and a map from each of its lines to where
+-- it starts in `file`, as a table with a `line` and, if the line starts partway through one of `file`, a `column`.
+-- Only the strings change, and they keep all of their line breaks, but each interpolated expression is put on a line
+-- of its own, as is the code after it. Lua reports where errors occur by line only, so this is what lets the runtime
+-- point to the expression that failed.
 local function process_file(file)
+    local markers = Markers(file)
     local buf = {}
     local pos = 1
     while true do
@@ -77,11 +166,11 @@ local function process_file(file)
         local cs, ce = find_close(file, os, oe, stype)
         if not cs then break end
         utils.append(buf, file:sub(pos, os - 1))
-        utils.append(buf, expand_string(file:sub(os, ce), file:sub(os, oe), file:sub(cs, ce), stype[4]))
+        utils.append(buf, expand_string(file:sub(os, ce), os, file:sub(os, oe), file:sub(cs, ce), stype[4], markers))
         pos = ce + 1
     end
     utils.append(buf, file:sub(pos))
-    return table.concat(buf)
+    return markers.resolve(table.concat(buf))
 end
 
 
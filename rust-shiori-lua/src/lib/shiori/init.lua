@@ -1,9 +1,12 @@
 local utils = rsl_require("utils")
 local events = rsl_require("events")
 local interface = rsl_require("shiori.interface")
+local response = rsl_require("shiori.response")
+local teach = rsl_require("shiori.teach")
+local scheduler = rsl_require("scheduler")
 
 local SYSTEM_EVENTS = {
-    "OnInitialize", "OnDestroy", "OnUserInput", "OnUserInputCancel", "inputbox.autocomplete",
+    "OnInitialize", "OnDestroy", "OnUserInput", "OnUserInputCancel", "inputbox.autocomplete", "OnShioriReload",
 }
 
 local shiori = {
@@ -11,6 +14,23 @@ local shiori = {
     resume_on_events = events.resume_on_events,
     set_event_preprocessor = events.set_event_preprocessor,
 
+    wait = scheduler.wait,
+    after = scheduler.after,
+    every = scheduler.every,
+    at = scheduler.at,
+
+    teach = {
+        ask = teach.ask,
+        advise = teach.advise,
+    },
+
+    -- Exposes only the functions scripts may call, not the runtime's state.
+    response = {
+        header = response.header,
+        passthru = response.passthru,
+        status = response.status,
+    },
+
     CharacterSet = function(...) return interface.CharacterSet(utils.Set{...}) end,
 
     -- The main way for ghosts to register event handlers is by assigning to functions to this table.
@@ -20,10 +40,22 @@ local shiori = {
                 shiori.script_error(("Cannot register handler for %s because it is a system event!"):format(e))
             end
             if type(e) == "string" and type(handler) == "function" then
-                events.push_static_event_handler(e, handler)
+                events.push_script_event_handler(e, handler)
             end
         end
-    })
+    }),
+
+    -- Like `event`, but for requests from outside the machine (`SecurityLevel: external`), e.g. SSTP over a network.
+    external_event = setmetatable({}, {
+        __newindex = function(_, e, handler)
+            if utils.contains(SYSTEM_EVENTS, e) then 
+                shiori.script_error(("Cannot register handler for %s because it is a system event!"):format(e))
+            end
+            if type(e) == "string" and type(handler) == "function" then
+                events.push_external_event_handler(e, handler)
+            end
+        end
+    }),
 }
 
 function shiori.bad_request(message, level)
@@ -31,6 +63,16 @@ function shiori.bad_request(message, level)
     error({message=message or "", code=400}, 1 + level)
 end
 
+-- Sets `key` (e.g. "lua.autosave") to `value` in profile/script-config.toml. Takes effect the next time the SHIORI
+-- is loaded.
+function shiori.set_config(key, value)
+    if type(key) ~= "string" then
+        error(("bad argument #1 to 'set_config' (string expected, got %s)"):format(type(key)), 2)
+    end
+    local ok, err = _set_config(key, value)
+    if not ok then error(err, 2) end
+end
+
 function shiori.script_error(message, level)
     level = level or 1
     error({message=message or "", code=500}, 1 + level)
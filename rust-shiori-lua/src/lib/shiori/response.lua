@@ -0,0 +1,40 @@
+-- Extra fields and an overriding status code for the response to the current request, which the runtime hands
+-- to rust-shiori-lua along with the script.
+local response = {
+    headers = {},
+    code = nil,
+}
+
+local PASSTHRU_PREFIX = "X-SSTP-PassThru-"
+
+-- Charset is always UTF-8, and Value is the script built during a GET or TEACH request.
+local RESERVED_HEADERS = { Charset = true, Value = true }
+local VALID_CODES = { [200] = true, [204] = true, [311] = true, [312] = true, [400] = true, [500] = true }
+
+function response.reset()
+    response.headers = {}
+    response.code = nil
+end
+
+function response.header(name, value)
+    if type(name) ~= "string" or name == "" or name:find("[:\r\n]") then
+        error(("Invalid response header name %q!"):format(tostring(name)), 2)
+    end
+    if RESERVED_HEADERS[name] then error(("The %s header cannot be set by scripts!"):format(name), 2) end
+    if value ~= nil then
+        value = tostring(value)
+        if value:find("[\r\n]") then error(("The value of response header %s contains a line break!"):format(name), 2) end
+    end
+    response.headers[name] = value
+end
+
+function response.passthru(name, value)
+    response.header(PASSTHRU_PREFIX .. tostring(name), value)
+end
+
+function response.status(code)
+    if not VALID_CODES[code] then error(("%s is not a valid SHIORI status code!"):format(tostring(code)), 2) end
+    response.code = code
+end
+
+return response
@@ -0,0 +1,37 @@
+-- TEACH requests are sent when the user types into the baseware's teach box. They carry no ID, so the runtime
+-- handles them as the event OnTeach. A handler may respond with 311 Not Enough or 312 Advice to ask the user for
+-- another word, in which case the baseware sends another TEACH request with the words so far in Reference1 onward.
+local events = rsl_require("events")
+local response = rsl_require("shiori.response")
+
+local teach = {}
+
+-- Adds the word the user taught (`word`), and the words taught earlier in the same conversation, most recent first
+-- (`history`), to the event table of a TEACH request.
+function teach.prepare(event)
+    event.ID = event.ID or "OnTeach"
+    event.id = event.ID
+    event.word = event.Reference0
+    event.history = {}
+    local n = 1
+    while event["Reference" .. n] do
+        event.history[n] = event["Reference" .. n]
+        n = n + 1
+    end
+    return event
+end
+
+local function continue(code)
+    response.status(code)
+    local event = events.resume_on_event("OnTeach", function(e) return e.history and e.history[1] ~= nil end)
+    return event.word, event
+end
+
+-- Ends the current response with 311 Not Enough, and waits for the user to teach another word, which is returned
+-- along with the event table of the request that brought it.
+function teach.ask() return continue(311) end
+
+-- Like `ask`, but responds with 312 Advice.
+function teach.advise() return continue(312) end
+
+return teach
@@ -0,0 +1,100 @@
+local logger = rsl_require("logger")
+local events = rsl_require("events")
+
+-- Orders table keys so that traversing the same state always visits them in the same order.
+local function key_less(a, b)
+    local ta, tb = type(a), type(b)
+    if ta ~= tb then return ta < tb end
+    if ta == "string" or ta == "number" then return a < b end
+    if ta == "boolean" then return b and not a end
+    return false
+end
+
+local function child_name(name, key, index)
+    if type(key) == "string" then return name .. "." .. key end
+    if type(key) == "number" or type(key) == "boolean" then return ("%s[%s]"):format(name, key) end
+    return ("%s[?%d]"):format(name, index)
+end
+
+-- Names every table, function and userdata reachable from `roots` (a list of `{ name, value }`) through table
+-- entries, metatables and upvalues, by the path it was first reached by. Eris saves these by name rather than by
+-- value, so that restored scripts share them with the runtime and the freshly loaded scripts instead of getting
+-- copies. The traversal is breadth-first over sorted keys, so the same state gets the same names in every session.
+-- Values in `skip` and everything only reachable through them are left out.
+local function name_permanents(roots, skip)
+    local names, queue, first = {}, {}, 1
+    local function visit(value, name)
+        local t = type(value)
+        if (t == "table" or t == "function" or t == "userdata") and not names[value] and not skip[value] then
+            names[value] = name
+            queue[#queue + 1] = value
+        end
+    end
+
+    for _, root in ipairs(roots) do visit(root[2], root[1]) end
+    while first <= #queue do
+        local value = queue[first]
+        local name = names[value]
+        first = first + 1
+        if type(value) == "table" then
+            local keys = {}
+            for key in next, value do keys[#keys + 1] = key end
+            table.sort(keys, key_less)
+            for i, key in ipairs(keys) do
+                local child = child_name(name, key, i)
+                visit(key, child .. "#key")
+                visit(rawget(value, key), child)
+            end
+        elseif type(value) == "function" then
+            local i = 1
+            while true do
+                local upname, upvalue = debug.getupvalue(value, i)
+                if upname == nil then break end
+                visit(upvalue, ("%s#%s%d"):format(name, upname, i))
+                i = i + 1
+            end
+        end
+        visit(debug.getmetatable(value), name .. "#meta")
+    end
+    return names
+end
+
+return {
+    -- Saves the scripts waiting in `resume_on_event(s)` to `file` when the ghost closes, and restores them when it
+    -- starts again. `roots` and `skip` are functions returning the arguments to `name_permanents`, since the values
+    -- they contain change when the scripts are reloaded.
+    Suspended = function(file, roots, skip)
+        local suspended = {}
+
+        function suspended.save()
+            local waits = {}
+            for wait in pairs(events.suspended) do waits[#waits + 1] = wait end
+            local permanents = name_permanents(roots(), skip())
+            local ok, data = pcall(file.serialize, file, waits, permanents)
+            if not ok then
+                logger.warn("Could not persist suspended scripts: %s", data)
+                return
+            end
+            local ok, err = file:save(data)
+            if ok then
+                logger.debug("Saved %d suspended scripts.", #waits)
+            else
+                logger.warn("Failed to save suspended scripts: %s", err)
+            end
+        end
+
+        function suspended.load()
+            local permanents = {}
+            for value, name in pairs(name_permanents(roots(), skip())) do permanents[name] = value end
+            local waits, err = file:load(permanents)
+            if waits then
+                events.restore_suspended(waits)
+                logger.info("Restored %d suspended scripts.", #waits)
+            elseif err then
+                logger.warn("Failed to restore suspended scripts: %s", err)
+            end
+        end
+
+        return suspended
+    end
+}
@@ -28,7 +28,7 @@ function utils.extend(a1, a2)
 end
 
 function utils.dup(array)
-    new = {}
+    local new = {}
     utils.extend(new, array)
     return new
 end
@@ -72,6 +72,22 @@ function utils.set_to_string(set)
     return table.concat(utils.set_to_table(set), ", ")
 end
 
+-- Returns a view of `table` and the tables in it that raises an error on assignment. `name` is used in the error.
+function utils.readonly(table, name)
+    local entries = {}
+    for key, value in pairs(table) do
+        if type(value) == "table" then value = utils.readonly(value, ("%s.%s"):format(name, key)) end
+        entries[key] = value
+    end
+    return setmetatable({}, {
+        __index = entries,
+        __newindex = function(_, key) error(("%s is read-only, cannot set %s"):format(name, tostring(key)), 2) end,
+        __pairs = function() return next, entries, nil end,
+        __len = function() return #entries end,
+        __metatable = false,
+    })
+end
+
 function utils.StringBuilder()
     local strings = {}
     return {
@@ -81,8 +97,24 @@ function utils.StringBuilder()
     }
 end
 
+-- Copies `value` and every table reachable from it, keys included. Tables that appear more than once, or contain
+-- themselves, do so in the copy too. Metatables, functions and userdata are shared with the original.
+function utils.deep_copy(value, copies)
+    if type(value) ~= "table" then return value end
+    copies = copies or {}
+    if copies[value] then return copies[value] end
+    local copy = {}
+    copies[value] = copy
+    for key, item in next, value do rawset(copy, utils.deep_copy(key, copies), utils.deep_copy(item, copies)) end
+    return setmetatable(copy, debug.getmetatable(value))
+end
+
 function utils.istable(obj) return type(obj) == "table" end
 
 function utils.second(_, b) return b end
 
+-- The current time, as returned by `os.time`, unless the host has set `_clock` to a clock of its own (as
+-- `shiori-runner` does, so that its ticks pass as whole seconds).
+function utils.now() return (_clock or os.time)() end
+
 return utils
\ No newline at end of file
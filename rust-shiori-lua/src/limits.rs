@@ -0,0 +1,99 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rlua::{HookTriggers, Lua, Context};
+
+use crate::config;
+
+/// How many VM instructions run between checks of the budget.
+const HOOK_INTERVAL: u32 = 1000;
+
+/// Limits how long a single request may run for, by raising an error from a Lua hook once its budget is spent. The
+/// error is raised again every time the hook runs until the request ends, and the runtime makes `pcall` and
+/// `coroutine.resume` rethrow it (see `_budget_exceeded`), so scripts can't catch it and keep running.
+#[derive(Clone, Default)]
+pub struct Budget {
+    state: Arc<Mutex<BudgetState>>,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    event: Option<String>,
+    started: Option<Instant>,
+    instructions: u64,
+    time_limit: Option<Duration>,
+    instruction_limit: Option<u64>,
+}
+
+impl Budget {
+    /// Applies the configured limits to `lua`. The memory limit applies at all times; the time and instruction
+    /// limits only between calls to `start` and `stop`.
+    pub fn install(lua: &Lua, limits: &config::Limits) -> Self {
+        let budget = Budget::default();
+        {
+            let mut state = budget.state.lock().unwrap();
+            state.time_limit = Some(limits.time).filter(|&t| t != 0).map(Duration::from_millis);
+            state.instruction_limit = Some(limits.instructions).filter(|&i| i != 0);
+        }
+        lua.set_memory_limit(Some(limits.memory).filter(|&m| m != 0));
+
+        if limits.time != 0 || limits.instructions != 0 {
+            let hook_budget = budget.clone();
+            let triggers = HookTriggers { every_nth_instruction: Some(HOOK_INTERVAL), .. HookTriggers::default() };
+            lua.set_hook(triggers, move |_, debug| {
+                match hook_budget.check(HOOK_INTERVAL as u64) {
+                    Some(exceeded) => {
+                        let source = debug.source();
+                        let location = String::from_utf8_lossy(source.short_src.unwrap_or(b"?"));
+                        Err(rlua::Error::RuntimeError(format!("{} at {}:{}.", exceeded, location, debug.curr_line())))
+                    },
+                    None => Ok(()),
+                }
+            });
+        }
+        budget
+    }
+
+    /// Exposes `exceeded` and `stop` to Lua as the globals `_budget_exceeded` and `_budget_stop`. The runtime stops the
+    /// budget once a script has failed, so that the hook doesn't fire again while it reports the error.
+    pub fn create_lua_interface(&self, ctx: &Context) -> rlua::Result<()> {
+        let budget = self.clone();
+        ctx.globals().set("_budget_exceeded", ctx.create_function(move |_, ()| Ok(budget.exceeded()))?)?;
+        let budget = self.clone();
+        ctx.globals().set("_budget_stop", ctx.create_function(move |_, ()| { budget.stop(); Ok(()) })?)
+    }
+
+    /// Starts a fresh budget for handling `event`.
+    pub fn start(&self, event: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.event = Some(event.to_string());
+        state.started = Some(Instant::now());
+        state.instructions = 0;
+    }
+
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.event = None;
+        state.started = None;
+    }
+
+    /// Describes the limit that the current request has exceeded, if any.
+    pub fn exceeded(&self) -> Option<String> {
+        self.check(0)
+    }
+
+    /// Counts `instructions` against the budget, and describes the limit that was exceeded, if any.
+    fn check(&self, instructions: u64) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let started = state.started?;
+        state.instructions += instructions;
+        let event = state.event.as_deref().unwrap_or("?");
+        match (state.time_limit, state.instruction_limit) {
+            (Some(limit), _) if started.elapsed() > limit =>
+                Some(format!("{} exceeded its time limit of {} ms", event, limit.as_millis())),
+            (_, Some(limit)) if state.instructions > limit =>
+                Some(format!("{} exceeded its limit of {} instructions", event, limit)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{Local, NaiveDate};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rust_shiori::context::RequestContext;
+
+use crate::config::{self, LogFormat, Sink};
+use crate::error::LoadError;
+
+/// Installs the logger described by the `[logging]` section, unless every level is `off`.
+pub fn init(ghost_path: &Path, config: &config::Logging) -> Result<(), LoadError> {
+    let filters = Filters::new(config);
+    if filters.max_level() == LevelFilter::Off { return Ok(()) }
+
+    let file = LogFile::open(ghost_path.join(&config.path), config.max_size, config.rotate_daily, config.keep)?;
+    let sink = match &config.sink {
+        None => None,
+        Some(Sink::Stderr) => Some(SinkWriter::Stderr),
+        Some(Sink::Udp { address }) => {
+            let local = if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+            let socket = UdpSocket::bind(local)?;
+            socket.connect(address)?;
+            Some(SinkWriter::Udp(socket))
+        }
+    };
+
+    log::set_max_level(filters.max_level());
+    let logger = Logger { filters, format: config.format, file: Mutex::new(file), sink };
+    log::set_boxed_logger(Box::new(logger))?;
+    Ok(())
+}
+
+/// Writes records that pass its filters to the log file, and to the second sink if there is one.
+struct Logger {
+    filters: Filters,
+    format: LogFormat,
+    file: Mutex<LogFile>,
+    sink: Option<SinkWriter>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filters.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) { return }
+
+        let context = RequestContext::current();
+        let line = match self.format {
+            LogFormat::Text => format_text(record, context),
+            LogFormat::Json => format_json(record, context),
+        };
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.write_line(&line); // There is nowhere to report a failure to log.
+        }
+        match &self.sink {
+            None => (),
+            Some(SinkWriter::Stderr) => eprint!("{}", line),
+            Some(SinkWriter::Udp(socket)) => { let _ = socket.send(line.as_bytes()); },
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+/// Formats a record as `12:34:56 [LEVEL] [#12 OnBoot] message`, where `#12 OnBoot` is the request being handled, if
+/// any. Trace records also show their target and location.
+fn format_text(record: &Record, context: Option<RequestContext>) -> String {
+    let mut line = format!("{} [{}] ", Local::now().format("%H:%M:%S"), record.level());
+    if let Some(context) = context {
+        line.push_str(&format!("[{}] ", context));
+    }
+    if record.level() == Level::Trace {
+        line.push_str(&format!("{}: [{}:", record.target(), record.file().unwrap_or("<unknown>")));
+        match record.line() {
+            Some(l) => line.push_str(&format!("{}] ", l)),
+            None => line.push_str("<unknown>] "),
+        }
+    }
+    line.push_str(&format!("{}\n", record.args()));
+    line
+}
+
+/// Formats a record as a line of JSON, with the fields `time`, `level`, `target`, `request`, `event`, `file`, `line`
+/// and `message`. Fields that are unknown are null.
+fn format_json(record: &Record, context: Option<RequestContext>) -> String {
+    let (request, event) = match context {
+        Some(c) => (Some(c.id), c.event),
+        None => (None, None),
+    };
+    let json = serde_json::json!({
+        "time": Local::now().to_rfc3339(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "request": request,
+        "event": event,
+        "file": record.file(),
+        "line": record.line(),
+        "message": record.args().to_string(),
+    });
+    format!("{}\n", json)
+}
+
+/// The level of each target. A target's level applies to the targets under it too, e.g. `rust_shiori` also covers
+/// `rust_shiori::internals`, unless they have their own.
+struct Filters {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>, // Longest first, so the most specific match is found first.
+}
+
+impl Filters {
+    fn new(config: &config::Logging) -> Self {
+        let mut targets: Vec<_> = config.targets.iter().map(|(t, l)| (t.clone(), *l)).collect();
+        targets.sort_by_key(|(t, _)| std::cmp::Reverse(t.len()));
+        Filters { default: config.level, targets }
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets.iter()
+            .find(|(t, _)| target == t || (target.starts_with(t.as_str()) && target[t.len()..].starts_with("::")))
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, l)| *l).fold(self.default, std::cmp::max)
+    }
+}
+
+enum SinkWriter {
+    Stderr,
+    Udp(UdpSocket), // Connected to the configured address. Each record is sent as one datagram.
+}
+
+/// The log file, which is rotated when a session starts, when it grows past `max_size` bytes, and if `daily` is set,
+/// when the date changes. Rotated logs are renamed to `rust-shiori.log.1`, `rust-shiori.log.2` and so on, newest
+/// first, and only `keep` of them are kept.
+struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    daily: bool,
+    keep: usize,
+    file: File,
+    size: u64,
+    date: NaiveDate,
+}
+
+impl LogFile {
+    fn open(path: PathBuf, max_size: u64, daily: bool, keep: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Self::rotate_files(&path, keep);
+        let file = File::create(&path)?;
+        Ok(LogFile {
+            path,
+            max_size,
+            daily,
+            keep,
+            file,
+            size: 0,
+            date: Local::now().date_naive(),
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let today = Local::now().date_naive();
+        let full = self.max_size != 0 && self.size != 0 && self.size + line.len() as u64 > self.max_size;
+        if full || (self.daily && today != self.date) {
+            self.file.flush()?;
+            Self::rotate_files(&self.path, self.keep);
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+            self.date = today;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts the rotated logs up by one, dropping the oldest, and makes the current log the newest. Failures are
+    /// ignored, since the worst outcome is losing an old log.
+    fn rotate_files(path: &Path, keep: usize) {
+        if keep == 0 { return }
+        let rotated = |n: usize| {
+            let mut name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+            name.push(format!(".{}", n));
+            path.with_file_name(name)
+        };
+        for n in (1..keep).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+        let _ = fs::rename(path, rotated(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// An empty directory for the logs of `test`.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust-shiori-lua-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(dir: &Path, name: &str) -> Option<String> {
+        fs::read_to_string(dir.join(name)).ok()
+    }
+
+    fn logging(level: LevelFilter, targets: &[(&str, LevelFilter)]) -> config::Logging {
+        config::Logging {
+            level,
+            targets: targets.iter().map(|(t, l)| (t.to_string(), *l)).collect::<BTreeMap<_, _>>(),
+            path: PathBuf::from("rust-shiori.log"),
+            format: LogFormat::Text,
+            max_size: 0,
+            rotate_daily: false,
+            keep: 0,
+            sink: None,
+            record: None,
+        }
+    }
+
+    #[test]
+    fn rotates_when_the_log_grows_past_max_size() {
+        let dir = temp_dir("size");
+        let mut log = LogFile::open(dir.join("rust-shiori.log"), 12, false, 3).unwrap();
+        log.write_line("12345\n").unwrap();
+        log.write_line("6789\n").unwrap();
+        assert_eq!(read(&dir, "rust-shiori.log.1"), None);
+        log.write_line("abc\n").unwrap();
+        assert_eq!(read(&dir, "rust-shiori.log.1").unwrap(), "12345\n6789\n");
+        assert_eq!(read(&dir, "rust-shiori.log").unwrap(), "abc\n");
+
+        // A line longer than `max_size` still goes into a log of its own.
+        log.write_line("a line past the limit\n").unwrap();
+        log.write_line("next\n").unwrap();
+        assert_eq!(read(&dir, "rust-shiori.log.2").unwrap(), "abc\n");
+        assert_eq!(read(&dir, "rust-shiori.log.1").unwrap(), "a line past the limit\n");
+        assert_eq!(read(&dir, "rust-shiori.log").unwrap(), "next\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_daily_if_asked_to() {
+        let dir = temp_dir("daily");
+        let mut log = LogFile::open(dir.join("rust-shiori.log"), 0, false, 3).unwrap();
+        log.write_line("yesterday\n").unwrap();
+        log.date = log.date.pred_opt().unwrap();
+        log.write_line("today\n").unwrap();
+        assert_eq!(read(&dir, "rust-shiori.log.1"), None);
+        drop(log);
+
+        let mut log = LogFile::open(dir.join("rust-shiori.log"), 0, true, 3).unwrap();
+        log.write_line("yesterday\n").unwrap();
+        log.date = log.date.pred_opt().unwrap();
+        log.write_line("today\n").unwrap();
+        assert_eq!(read(&dir, "rust-shiori.log.1").unwrap(), "yesterday\n");
+        assert_eq!(read(&dir, "rust-shiori.log").unwrap(), "today\n");
+        assert_eq!(log.date, Local::now().date_naive());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_only_the_newest_logs() {
+        let dir = temp_dir("keep");
+        for session in 1..=4 {
+            let mut log = LogFile::open(dir.join("rust-shiori.log"), 0, false, 2).unwrap();
+            log.write_line(&format!("session {}\n", session)).unwrap();
+        }
+        assert_eq!(read(&dir, "rust-shiori.log").unwrap(), "session 4\n");
+        assert_eq!(read(&dir, "rust-shiori.log.1").unwrap(), "session 3\n");
+        assert_eq!(read(&dir, "rust-shiori.log.2").unwrap(), "session 2\n");
+        assert_eq!(read(&dir, "rust-shiori.log.3"), None);
+
+        // With `keep = 0`, each session overwrites the log.
+        let mut log = LogFile::open(dir.join("rust-shiori.log"), 0, false, 0).unwrap();
+        log.write_line("session 5\n").unwrap();
+        assert_eq!(read(&dir, "rust-shiori.log").unwrap(), "session 5\n");
+        assert_eq!(read(&dir, "rust-shiori.log.1").unwrap(), "session 3\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn targets_have_their_own_levels() {
+        let filters = Filters::new(&logging(LevelFilter::Warn, &[
+            ("lua", LevelFilter::Debug),
+            ("lua::persistent", LevelFilter::Error),
+            ("rust_shiori", LevelFilter::Trace),
+        ]));
+        assert_eq!(filters.level("lua"), LevelFilter::Debug);
+        assert_eq!(filters.level("lua::script"), LevelFilter::Debug);
+        assert_eq!(filters.level("lua::persistent"), LevelFilter::Error);
+        assert_eq!(filters.level("lua::persistent::eris"), LevelFilter::Error);
+        assert_eq!(filters.level("luajit"), LevelFilter::Warn);
+        assert_eq!(filters.level("rust_shiori::internals"), LevelFilter::Trace);
+        assert_eq!(filters.level("rust_shiori_lua"), LevelFilter::Warn);
+        assert_eq!(filters.max_level(), LevelFilter::Trace);
+
+        assert_eq!(Filters::new(&logging(LevelFilter::Off, &[])).max_level(), LevelFilter::Off);
+    }
+
+    #[test]
+    fn records_are_also_sent_to_the_sink() {
+        let dir = temp_dir("sink");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+
+        let logger = |sink| Logger {
+            filters: Filters::new(&logging(LevelFilter::Info, &[("quiet", LevelFilter::Error)])),
+            format: LogFormat::Text,
+            file: Mutex::new(LogFile::open(dir.join("rust-shiori.log"), 0, false, 0).unwrap()),
+            sink: Some(sink),
+        };
+        let log = |logger: &Logger, target: &str, message: &str| {
+            logger.log(&Record::builder().args(format_args!("{}", message)).level(Level::Info).target(target).build());
+        };
+
+        let udp = logger(SinkWriter::Udp(sender));
+        log(&udp, "quiet", "filtered out");
+        log(&udp, "lua", "sent");
+        let mut datagram = [0; 256];
+        let size = receiver.recv(&mut datagram).unwrap();
+        let sent = String::from_utf8_lossy(&datagram[..size]);
+        assert!(sent.ends_with(" [INFO] sent\n"), "{}", sent);
+        assert_eq!(read(&dir, "rust-shiori.log").unwrap(), sent);
+
+        let stderr = logger(SinkWriter::Stderr);
+        log(&stderr, "lua", "printed");
+        assert!(read(&dir, "rust-shiori.log").unwrap().ends_with(" [INFO] printed\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
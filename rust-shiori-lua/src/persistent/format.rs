@@ -0,0 +1,367 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rlua::{Context, Table, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::eris;
+
+/// How persistent data is serialized. Eris can save almost any Lua value, but its output is an opaque binary blob
+/// tied to the exact Lua build. The text formats only support tables, strings, numbers and booleans, but can be read
+/// and edited by hand.
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Eris,
+    Json,
+    Toml,
+    Lua,
+}
+
+/// Eris prefixes everything it persists with this.
+const ERIS_HEADER: &[u8] = b"ERIS";
+
+/// Saves in the Lua format are a chunk returning the data.
+const LUA_PREFIX: &str = "return ";
+
+/// Tables nested deeper than this are assumed to contain a cycle.
+const MAX_DEPTH: usize = 100;
+
+impl Format {
+    /// Guesses the format of saved data, so that saves in one format can be loaded after switching to another.
+    pub fn detect(data: &[u8]) -> Format {
+        if data.starts_with(ERIS_HEADER) { return Format::Eris }
+        let text = String::from_utf8_lossy(data);
+        let text = text.trim_start();
+        if is_lua(text) {
+            Format::Lua
+        } else if text.starts_with('{') {
+            Format::Json
+        } else {
+            Format::Toml
+        }
+    }
+
+    /// Serializes `data`. For Eris, values that are keys of `permanents` are saved as the corresponding value instead.
+    pub fn serialize<'lua>(self, ctx: Context<'lua>, data: Value<'lua>, permanents: Option<Table<'lua>>)
+        -> Result<Vec<u8>, String>
+    {
+        if self == Format::Eris {
+            let data = eris::persist(ctx, permanents, data).map_err(|e| e.to_string())?;
+            return Ok(data.as_bytes().to_vec())
+        }
+
+        let data = Data::from_lua(data, "persistent", 0)?;
+        match self {
+            Format::Json => serde_json::to_vec_pretty(&data.to_json("persistent")?).map_err(|e| e.to_string()),
+            Format::Toml => match data.to_toml("persistent")? {
+                // The serializer leaves DEL unescaped, which the parser then rejects. It always quotes strings with
+                // `"`, so DEL can be escaped wherever it appears.
+                table @ toml::Value::Table(_) => toml::to_string(&table)
+                    .map(|text| text.replace('\u{7f}', "\\u007F").into_bytes())
+                    .map_err(|e| e.to_string()),
+                _ => Err("persistent must have string keys to be saved as TOML".to_string()),
+            },
+            Format::Lua => {
+                let mut text = LUA_PREFIX.as_bytes().to_vec();
+                data.write_lua(&mut text, 0);
+                text.push(b'\n');
+                Ok(text)
+            },
+            Format::Eris => unreachable!(),
+        }
+    }
+
+    /// Deserializes `data`. For Eris, `permanents` maps the values saved in place of permanent values back to them.
+    pub fn deserialize<'lua>(self, ctx: Context<'lua>, data: &[u8], permanents: Option<Table<'lua>>)
+        -> Result<Value<'lua>, String>
+    {
+        let text = || std::str::from_utf8(data).map_err(|e| e.to_string());
+        let result = match self {
+            Format::Eris => eris::unpersist(ctx, permanents, data),
+            Format::Json => json_to_lua(ctx, serde_json::from_slice(data).map_err(|e| e.to_string())?),
+            Format::Toml => toml_to_lua(ctx, text()?.parse().map_err(|e: toml::de::Error| e.to_string())?),
+            Format::Lua => {
+                // The chunk gets an empty environment, so it can build tables but not call anything.
+                let env = ctx.create_table().map_err(|e| e.to_string())?;
+                ctx.load(data).set_name("=persistent data").and_then(|c| c.set_environment(env)).and_then(|c| c.eval())
+            },
+        };
+        result.map_err(|e| e.to_string())
+    }
+}
+
+/// Whether `text` starts like a Lua chunk: with a comment, or with `return` that isn't the start of a TOML key.
+fn is_lua(text: &str) -> bool {
+    if text.starts_with("--") { return true }
+    match text.strip_prefix(LUA_PREFIX.trim_end()) {
+        Some(rest) => !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+            && !rest.trim_start().starts_with(['=', '.']),
+        None => false,
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Format::Eris => "Eris",
+            Format::Json => "JSON",
+            Format::Toml => "TOML",
+            Format::Lua => "Lua",
+        })
+    }
+}
+
+/// The subset of Lua values that the text formats can represent. Table entries are sorted, so that the same data
+/// always serializes the same way.
+#[derive(PartialEq)]
+enum Data {
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Vec<u8>),
+    Table(Vec<(Data, Data)>),
+}
+
+impl Data {
+    fn from_lua(value: Value, path: &str, depth: usize) -> Result<Data, String> {
+        Ok(match value {
+            Value::Boolean(b) => Data::Boolean(b),
+            Value::Integer(i) => Data::Integer(i),
+            Value::Number(n) => Data::Number(n),
+            Value::String(s) => Data::String(s.as_bytes().to_vec()),
+            Value::Table(table) => {
+                if depth >= MAX_DEPTH {
+                    return Err(format!("{} is nested too deeply, or contains itself", path))
+                }
+                let mut entries = Vec::new();
+                for pair in table.pairs::<Value, Value>() {
+                    let (key, value) = pair.map_err(|e| e.to_string())?;
+                    let key = match key {
+                        Value::Boolean(_) | Value::Integer(_) | Value::Number(_) | Value::String(_) =>
+                            Data::from_lua(key, path, depth + 1)?,
+                        other => return Err(format!("{} has a key of type {}, which can't be saved", path, type_name(&other))),
+                    };
+                    let value = Data::from_lua(value, &format!("{}{}", path, key.path_suffix()), depth + 1)?;
+                    entries.push((key, value));
+                }
+                entries.sort_by(|(a, _), (b, _)| a.key_order(b));
+                Data::Table(entries)
+            },
+            other => return Err(format!("{} is a {}, which can't be saved in a text format", path, type_name(&other))),
+        })
+    }
+
+    fn path_suffix(&self) -> String {
+        match self {
+            Data::String(s) => format!(".{}", String::from_utf8_lossy(s)),
+            Data::Integer(i) => format!("[{}]", i),
+            Data::Number(n) => format!("[{}]", n),
+            Data::Boolean(b) => format!("[{}]", b),
+            Data::Table(_) => "[table]".to_string(),
+        }
+    }
+
+    /// Orders numbers before strings before booleans.
+    fn key_order(&self, other: &Data) -> Ordering {
+        fn rank(data: &Data) -> (u8, f64) {
+            match data {
+                Data::Integer(i) => (0, *i as f64),
+                Data::Number(n) => (0, *n),
+                Data::String(_) => (1, 0.0),
+                Data::Boolean(b) => (2, *b as u8 as f64),
+                Data::Table(_) => (3, 0.0),
+            }
+        }
+        match (self, other) {
+            (Data::Integer(a), Data::Integer(b)) => a.cmp(b),
+            (Data::String(a), Data::String(b)) => a.cmp(b),
+            _ => {
+                let (a, b) = (rank(self), rank(other));
+                a.0.cmp(&b.0).then(a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+            },
+        }
+    }
+
+    /// The values of a table whose keys are exactly 1 to n, if it's one.
+    fn as_sequence(entries: &[(Data, Data)]) -> Option<Vec<&Data>> {
+        if entries.is_empty() { return None }
+        entries.iter().enumerate()
+            .map(|(i, (key, value))| if *key == Data::Integer(i as i64 + 1) { Some(value) } else { None })
+            .collect()
+    }
+
+    /// The string keys of a table, failing if it has any other keys.
+    fn string_entries<'a>(entries: &'a [(Data, Data)], path: &str) -> Result<Vec<(String, &'a Data)>, String> {
+        entries.iter().map(|(key, value)| match key {
+            Data::String(s) => String::from_utf8(s.clone()).map(|s| (s, value))
+                .map_err(|_| format!("{} has a key that isn't valid UTF-8", path)),
+            _ => Err(format!("{} has non-string keys, but isn't a sequence", path)),
+        }).collect()
+    }
+
+    fn utf8(s: &[u8], path: &str) -> Result<String, String> {
+        String::from_utf8(s.to_vec()).map_err(|_| format!("{} isn't valid UTF-8", path))
+    }
+
+    fn finite(n: f64, path: &str) -> Result<f64, String> {
+        if n.is_finite() { Ok(n) } else { Err(format!("{} is {}, which can't be saved", path, n)) }
+    }
+
+    fn to_json(&self, path: &str) -> Result<serde_json::Value, String> {
+        use serde_json::Value as Json;
+        Ok(match self {
+            Data::Boolean(b) => Json::Bool(*b),
+            Data::Integer(i) => Json::from(*i),
+            Data::Number(n) => Json::from(Data::finite(*n, path)?),
+            Data::String(s) => Json::String(Data::utf8(s, path)?),
+            Data::Table(entries) => match Data::as_sequence(entries) {
+                Some(values) => Json::Array(values.iter().enumerate()
+                    .map(|(i, v)| v.to_json(&format!("{}[{}]", path, i + 1)))
+                    .collect::<Result<_, _>>()?),
+                None => Json::Object(Data::string_entries(entries, path)?.into_iter()
+                    .map(|(k, v)| v.to_json(&format!("{}.{}", path, k)).map(|v| (k, v)))
+                    .collect::<Result<_, _>>()?),
+            },
+        })
+    }
+
+    fn to_toml(&self, path: &str) -> Result<toml::Value, String> {
+        use toml::Value as Toml;
+        Ok(match self {
+            Data::Boolean(b) => Toml::Boolean(*b),
+            Data::Integer(i) => Toml::Integer(*i),
+            Data::Number(n) => Toml::Float(Data::finite(*n, path)?),
+            Data::String(s) => Toml::String(Data::utf8(s, path)?),
+            Data::Table(entries) => match Data::as_sequence(entries) {
+                Some(values) => {
+                    let values = values.iter().enumerate()
+                        .map(|(i, v)| v.to_toml(&format!("{}[{}]", path, i + 1)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if values.iter().any(|v| v.type_str() != values[0].type_str()) {
+                        return Err(format!("{} mixes types of values, which TOML arrays can't", path))
+                    }
+                    Toml::Array(values)
+                },
+                None => Toml::Table(Data::string_entries(entries, path)?.into_iter()
+                    .map(|(k, v)| v.to_toml(&format!("{}.{}", path, k)).map(|v| (k, v)))
+                    .collect::<Result<BTreeMap<_, _>, _>>()?),
+            },
+        })
+    }
+
+    /// Writes the value as a Lua expression, indenting nested tables by `indent` levels.
+    fn write_lua(&self, out: &mut Vec<u8>, indent: usize) {
+        match self {
+            Data::Boolean(b) => out.extend_from_slice(b.to_string().as_bytes()),
+            // The literal for the minimum integer would be read as a float.
+            Data::Integer(i64::MIN) => out.extend_from_slice(b"(-9223372036854775807 - 1)"),
+            Data::Integer(i) => out.extend_from_slice(i.to_string().as_bytes()),
+            Data::Number(n) if n.is_nan() => out.extend_from_slice(b"(0/0)"),
+            Data::Number(n) if n.is_infinite() => out.extend_from_slice(if *n > 0.0 { b"(1/0)" } else { b"(-1/0)" }),
+            Data::Number(n) => out.extend_from_slice(format!("{:?}", n).as_bytes()),
+            Data::String(s) => write_lua_string(out, s),
+            Data::Table(entries) if entries.is_empty() => out.extend_from_slice(b"{}"),
+            Data::Table(entries) => {
+                let sequence = Data::as_sequence(entries).is_some();
+                out.extend_from_slice(b"{\n");
+                for (key, value) in entries {
+                    out.extend(std::iter::repeat_n(b' ', (indent + 1) * 4));
+                    if !sequence {
+                        match key {
+                            Data::String(s) if is_identifier(s) => out.extend_from_slice(s),
+                            _ => {
+                                out.push(b'[');
+                                key.write_lua(out, indent + 1);
+                                out.push(b']');
+                            },
+                        }
+                        out.extend_from_slice(b" = ");
+                    }
+                    value.write_lua(out, indent + 1);
+                    out.extend_from_slice(b",\n");
+                }
+                out.extend(std::iter::repeat_n(b' ', indent * 4));
+                out.push(b'}');
+            },
+        }
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Boolean(_) => "boolean",
+        Value::LightUserData(_) | Value::UserData(_) => "userdata",
+        Value::Integer(_) | Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Table(_) => "table",
+        Value::Function(_) => "function",
+        Value::Thread(_) => "thread",
+        Value::Error(_) => "error",
+    }
+}
+
+const LUA_KEYWORDS: &[&[u8]] = &[
+    b"and", b"break", b"do", b"else", b"elseif", b"end", b"false", b"for", b"function", b"goto", b"if", b"in",
+    b"local", b"nil", b"not", b"or", b"repeat", b"return", b"then", b"true", b"until", b"while",
+];
+
+fn is_identifier(s: &[u8]) -> bool {
+    match s.first() {
+        Some(c) if c.is_ascii_alphabetic() || *c == b'_' =>
+            s.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_') && !LUA_KEYWORDS.contains(&s),
+        _ => false,
+    }
+}
+
+/// Writes `s` as a quoted Lua string. Bytes outside of ASCII are written as-is, so that UTF-8 text stays readable.
+fn write_lua_string(out: &mut Vec<u8>, s: &[u8]) {
+    out.push(b'"');
+    for &c in s {
+        match c {
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            // Always three digits, so that a digit following the escape isn't read as part of it.
+            c if c < 0x20 || c == 0x7f => out.extend_from_slice(format!("\\{:03}", c).as_bytes()),
+            c => out.push(c),
+        }
+    }
+    out.push(b'"');
+}
+
+fn json_to_lua(ctx: Context, value: serde_json::Value) -> rlua::Result<Value> {
+    use serde_json::Value as Json;
+    Ok(match value {
+        Json::Null => Value::Nil,
+        Json::Bool(b) => Value::Boolean(b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Json::String(s) => Value::String(ctx.create_string(&s)?),
+        Json::Array(values) => Value::Table(ctx.create_sequence_from(
+            values.into_iter().map(|v| json_to_lua(ctx, v)).collect::<rlua::Result<Vec<_>>>()?)?),
+        Json::Object(entries) => Value::Table(ctx.create_table_from(
+            entries.into_iter().map(|(k, v)| json_to_lua(ctx, v).map(|v| (k, v))).collect::<rlua::Result<Vec<_>>>()?)?),
+    })
+}
+
+pub fn toml_to_lua(ctx: Context, value: toml::Value) -> rlua::Result<Value> {
+    use toml::Value as Toml;
+    Ok(match value {
+        Toml::Boolean(b) => Value::Boolean(b),
+        Toml::Integer(i) => Value::Integer(i),
+        Toml::Float(n) => Value::Number(n),
+        Toml::String(s) => Value::String(ctx.create_string(&s)?),
+        Toml::Datetime(d) => Value::String(ctx.create_string(&d.to_string())?),
+        Toml::Array(values) => Value::Table(ctx.create_sequence_from(
+            values.into_iter().map(|v| toml_to_lua(ctx, v)).collect::<rlua::Result<Vec<_>>>()?)?),
+        Toml::Table(entries) => Value::Table(ctx.create_table_from(
+            entries.into_iter().map(|(k, v)| toml_to_lua(ctx, v).map(|v| (k, v))).collect::<rlua::Result<Vec<_>>>()?)?),
+    })
+}
@@ -0,0 +1,122 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+use rlua::{Table, UserData, UserDataMethods, Value};
+
+mod format;
+
+pub use self::format::{Format, toml_to_lua};
+
+/// The file that persistent data is saved to, along with its backups. Saves are atomic: the data is written to a
+/// temporary file, which then replaces the save file, and the previous save becomes the first backup. Exposed to
+/// the runtime's `persistent` and `suspended` modules.
+pub struct PersistentFile {
+    path: PathBuf,
+    backups: usize,
+    format: Format,
+}
+
+impl PersistentFile {
+    pub fn new(path: PathBuf, backups: usize, format: Format) -> Self {
+        PersistentFile { path, backups, format }
+    }
+
+    /// The save file with `suffix` appended to its name, e.g. `persistent.dat.1`.
+    fn with_suffix(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().map(OsString::from).unwrap_or_default();
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+
+    fn backup(&self, n: usize) -> PathBuf {
+        self.with_suffix(&format!(".{}", n))
+    }
+
+    pub fn save(&self, data: &[u8]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let temp = self.with_suffix(".tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        drop(file);
+
+        if self.backups > 0 {
+            for n in (1..self.backups).rev() {
+                rename_if_exists(&self.backup(n), &self.backup(n + 1))?;
+            }
+            rename_if_exists(&self.path, &self.backup(1))?;
+        }
+        fs::rename(&temp, &self.path)
+    }
+
+    /// The save file followed by its backups, newest first, skipping any that don't exist.
+    pub fn candidates(&self) -> Vec<PathBuf> {
+        std::iter::once(self.path.clone())
+            .chain((1..=self.backups).map(|n| self.backup(n)))
+            .filter(|p| p.is_file())
+            .collect()
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+impl UserData for PersistentFile {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Serializes `data` in the configured format, with the given Eris permanents, if any. Raises an error if
+        // `data` can't be represented in that format.
+        methods.add_method("serialize", |ctx, this, (data, permanents): (Value, Option<Table>)| {
+            let data = this.format.serialize(ctx, data, permanents).map_err(rlua::Error::RuntimeError)?;
+            ctx.create_string(&data)
+        });
+
+        // Returns true, or false and an error message.
+        methods.add_method("save", |_, this, data: rlua::String| {
+            match this.save(data.as_bytes()) {
+                Ok(()) => Ok((true, None)),
+                Err(e) => Ok((false, Some(format!("{}: {}", this.path.display(), e)))),
+            }
+        });
+
+        // Deserializes the save file, falling back to each backup in turn if it can't be read or deserialized. Each
+        // file is read in whichever format it was saved in, so that switching formats converts the data the next
+        // time it is saved. `permanents` is the inverse of the Eris permanents the data was saved with, if any.
+        // Returns the data, or nil and an error message if no file worked. If there is no save file or backup at all,
+        // returns nil and no message.
+        methods.add_method("load", |ctx, this, permanents: Option<Table>| {
+            let candidates = this.candidates();
+            if candidates.is_empty() {
+                debug!("No persistent data found at {}.", this.path.display());
+                return Ok((Value::Nil, None))
+            }
+            for path in &candidates {
+                let result = fs::read(path).map_err(|e| e.to_string())
+                    .and_then(|data| {
+                        let format = Format::detect(&data);
+                        format.deserialize(ctx, &data, permanents.clone()).map(|value| (value, format))
+                    });
+                match result {
+                    Ok((value, format)) => {
+                        if *path != this.path { warn!("Loaded persistent data from the backup {}.", path.display()) }
+                        if format != this.format {
+                            info!("Loaded persistent data saved as {}; it will be converted to {} when saved.", format, this.format);
+                        }
+                        return Ok((value, None))
+                    },
+                    Err(e) => warn!("Could not load persistent data from {}: {}", path.display(), e),
+                }
+            }
+            Ok((Value::Nil, Some("The save file and all of its backups are unreadable.".to_string())))
+        });
+    }
+}
@@ -1,6 +1,8 @@
-local ok_codes = { GET = 200, NOTIFY = 204 }
+local ok_codes = { GET = 200, NOTIFY = 204, TEACH = 200 }
+local script_methods = { GET = true, TEACH = true } -- Methods whose responses carry a script.
 
-local function Responder(init_module, searcher, persistent_path)
+local function Responder(searcher, options)
+    local init_module, sandbox = options.init, options.sandbox
     local loaded_rsl_modules = {}
     function _G.rsl_require(module)
         if not loaded_rsl_modules[module] then
@@ -18,61 +20,202 @@ local function Responder(init_module, searcher, persistent_path)
     local interpolate = rsl_require("script.interpolate")
     local dtags = rsl_require("script.dtags")
     local shiori = rsl_require("shiori")
+    local response = rsl_require("shiori.response")
+    local teach = rsl_require("shiori.teach")
     local Persistent = rsl_require("persistent").Persistent
+    local Suspended = rsl_require("suspended").Suspended
+    local report = rsl_require("report")
+    local positions = rsl_require("positions")
+    local scheduler = rsl_require("scheduler")
 
-    local persistent = Persistent(persistent_path)
+    -- Tracebacks point to where code is in the files it was loaded from, whoever makes them.
+    debug.traceback = positions.traceback
+    if sandbox and sandbox.debug then sandbox.debug.traceback = positions.traceback end
+
+    -- Events that requests with `SecurityLevel: external` may trigger through the usual handlers.
+    local allowed_external = {}
+    for _, id in ipairs(options.external_events) do allowed_external[id] = true end
+
+    -- The parsed configuration, which scripts can read but not change.
+    local config = utils.readonly(options.config, "config")
+
+    local persistent = Persistent(options.persistent, options.autosave)
     persistent.load()
     logger.debug("Loaded persistent data.")
-    events.push_static_event_handler("OnDestroy", persistent.save)
+    local suspended = nil -- Set below if suspended scripts are persisted.
+    -- Only the first handler of an event that returns a script runs, so everything is saved by one handler.
+    events.push_static_event_handler("OnDestroy", function()
+        if suspended then suspended.save() end
+        return persistent.save()
+    end)
     
     local interface = nil
+    local script_env = nil -- The environment of the current scripts.
+
+    -- Modules such as `io` and `eris` that were loaded or preloaded before any ghost code, which sandboxed code may
+    -- only require if the sandbox contains them.
+    local builtin_modules = {}
+    for module in pairs(package.loaded) do builtin_modules[module] = true end
+    for module in pairs(package.preload) do builtin_modules[module] = true end
+
+    local function sandboxed_require(module)
+        if builtin_modules[module] then
+            return sandbox[module] or error(("module '%s' is not available in the sandbox"):format(module), 2)
+        end
+        return require(module)
+    end
+
+    -- Lets pcall, xpcall and coroutines catch errors as usual, unless the request has exceeded its budget, in which
+    -- case the error is raised again so that a script can't keep running by catching it.
+    local function guard_protected_calls(env)
+        local function guarded(fn)
+            return function(...)
+                local results = table.pack(fn(...))
+                local exceeded = _budget_exceeded()
+                if exceeded then error(exceeded, 2) end
+                return table.unpack(results, 1, results.n)
+            end
+        end
+        env.pcall = guarded(pcall)
+        env.xpcall = guarded(xpcall)
+        local base_coroutine = (sandbox or _G).coroutine
+        if base_coroutine then
+            env.coroutine = {}
+            for name, fn in pairs(base_coroutine) do env.coroutine[name] = fn end
+            env.coroutine.resume = guarded(coroutine.resume)
+            env.coroutine.wrap = function(fn) return guarded(coroutine.wrap(fn)) end
+        end
+        return env
+    end
+
+    -- Adds the globals that sandboxed code needs bound to its own environment.
+    local function bind_sandbox(env)
+        env.load = function(chunk, name, _, chunk_env) return load(chunk, name, "t", chunk_env or env) end
+        env.require = sandboxed_require
+        return guard_protected_calls(env)
+    end
+
+    if sandbox then
+        -- Libraries share one environment, like they would share _G outside of the sandbox.
+        local library_env = bind_sandbox(setmetatable({}, { __index = sandbox }))
+        library_env._G = library_env
+        package.searchers[2] = function(module)
+            local path, err = package.searchpath(module, package.path)
+            if path == nil then return ("\n\t" .. err) end
+            local mod, err = loadfile(path, "t", library_env)
+            return mod or ("\n\t" .. err)
+        end
+        logger.debug("Sandboxed library searcher.")
+    end
 
     local function create_script_env()
         local script_env_meta = {
             __index = function(table, key)
                 if key == "rsl_require" then return nil end
                 if key == "script" then return interface end
-                return logger[key] or _G[key]
+                return logger[key] or (sandbox or _G)[key]
             end
         }
 
-        local script_env = {
-            _G = script_env,
+        script_env = {
             shiori = shiori,
 
             bad_request = shiori.bad_request,
             choose = utils.choose,
+            config = config,
             event = shiori.event,
+            external_event = shiori.external_event,
             persistent = persistent.data,
             script_error = shiori.script_error,
             
             _tags = dtags.public,
         }
+        script_env._G = script_env
+        if sandbox then bind_sandbox(script_env) else guard_protected_calls(script_env) end
 
         return setmetatable(script_env, script_env_meta)
     end
 
+    -- Names of the modules loaded by the current script searcher, which are unloaded when the scripts are reloaded.
+    local script_modules = {}
+
     local function ScriptSearcher()
         local env = create_script_env()
         logger.debug("Created script environment.")
 
         return function(module)
+            if options.watched_scripts then
+                -- Every file the module could be in is watched, since creating one may change which is loaded.
+                local name = module:gsub("%.", package.config:sub(1, 1))
+                for template in package.script_path:gmatch("[^;]+") do
+                    options.watched_scripts:add((template:gsub("%?", function() return name end)))
+                end
+            end
             local path, err = package.searchpath(module, package.script_path)
             if path == nil then return ("\n\t" .. err) end
-            local file, err = io.open(path, "r")
-            if file == nil then return ("\n\t" .. err) end
-            local script = interpolate.process_file(file:read("*all"))
-            local mod, err = load(script, "@" .. path, "t", env)
-            file:close()
-            return (not err and mod) or ("\n\t" .. err)
+            local bytecode = options.script_cache and options.script_cache:get(path)
+            local mod = bytecode and load(bytecode, "@" .. path, "b", env)
+            if mod then
+                -- The map is only needed for errors, so it isn't cached, and only made if there is one.
+                positions.add("@" .. path, function()
+                    local file = assert(io.open(path, "r"))
+                    local _, map = interpolate.process_file(file:read("*all"))
+                    file:close()
+                    return map
+                end)
+            else
+                local file, err = io.open(path, "r")
+                if file == nil then return ("\n\t" .. err) end
+                local script, map = interpolate.process_file(file:read("*all"))
+                file:close()
+                positions.add("@" .. path, map)
+                mod, err = load(script, "@" .. path, "t", env)
+                if not mod then return ("\n\t" .. positions.rewrite(err)) end
+                if options.script_cache then options.script_cache:put(path, string.dump(mod)) end
+            end
+            script_modules[module] = true
+            return mod
         end
     end
 
-    package.searchers[#package.searchers + 1] = ScriptSearcher()
+    local searcher_index = #package.searchers + 1
+    package.searchers[searcher_index] = ScriptSearcher()
     logger.debug("Installed script searcher.")
 
+    -- Loads the scripts again in a fresh environment, replacing every handler they registered. Handlers of suspended
+    -- scripts and persistent data are kept. If loading fails, everything is left as it was before.
+    local function reload()
+        local saved_handlers = events.remove_script_handlers()
+        local saved_timers = scheduler.remove_callbacks()
+        local saved_script_modules, saved_searcher = script_modules, package.searchers[searcher_index]
+        local saved_env = script_env
+        local saved_modules = {}
+        for module in pairs(script_modules) do
+            saved_modules[module] = package.loaded[module]
+            package.loaded[module] = nil
+        end
+
+        script_modules = {}
+        package.searchers[searcher_index] = ScriptSearcher()
+        local ok, err = xpcall(require, report.build, init_module)
+        if ok then
+            persistent.finish_migrations()
+            events.prioritize_kept_handlers(saved_handlers)
+            logger.info("Reloaded scripts.")
+            return true
+        end
+
+        for module in pairs(script_modules) do package.loaded[module] = nil end
+        for module, value in pairs(saved_modules) do package.loaded[module] = value end
+        script_modules, package.searchers[searcher_index], script_env = saved_script_modules, saved_searcher, saved_env
+        persistent.migrations = {}
+        events.restore_handlers(saved_handlers)
+        scheduler.restore_callbacks(saved_timers)
+        return false, err
+    end
+
     local function resume_script(routine, id, event, method)
-        if method == "GET" then script.current = script.Script() else script.current = nil end
+        if script_methods[method] then script.current = script.Script() else script.current = nil end
         interface = script.current and ScriptInterface()
 
         local s, e = coroutine.resume(routine, id, event)
@@ -80,40 +223,96 @@ local function Responder(init_module, searcher, persistent_path)
             if e == "cannot resume dead coroutine" then
                 return { text = "Attempt to resume a script that has already ended.", code = 500 }
             else
-                if (not utils.istable(e)) or (not e.message) then e = { message = e } end
-                local error_msg = ("%s\n%s"):format(utils.tostring_or_nil(e.message), debug.traceback(routine))
-                return { text = error_msg, code = e.code or 500 }
+                return { text = report.build(e, routine, id), code = utils.istable(e) and e.code or 500 }
             end
         end
         
         return { text = script.current and script.current.to_sakura(), code = ok_codes[method] }
     end
 
+    -- Calls the handlers in `handler_table` for the event `id` until one returns a response.
+    local function dispatch(handler_table, id, procevent, method)
+        local result = { text = nil, code = 204 }
+        -- Handlers may register more handlers for the same event while running, so iterate over a copy.
+        local handlers = utils.dup(handler_table[id] or {})
+        local removed = {}
+        for _, handler in ipairs(handlers) do
+            local routine, remove = handler(procevent)
+            if remove then removed[handler] = true end
+            if routine then 
+                result = resume_script(routine, id, procevent, method)
+                if result.code ~= 204 then break end
+            end
+        end
+        local remaining = {}
+        for _, handler in ipairs(handler_table[id] or {}) do
+            if not removed[handler] then remaining[#remaining + 1] = handler end
+        end
+        handler_table[id] = remaining
+        return result
+    end
+
     local function respond(event, method)
         local result = { text = nil, code = 204 }
-        if event and event["ID"] then
-            local preprocessor = events.event_preprocessors[event["ID"]]
-            local processed_event = {event}
+        response.reset()
+        if event and method == "TEACH" then event = teach.prepare(event) end
+        local id = event and event["ID"]
+        scheduler.advance()
+        local external = event and (event["SecurityLevel"] or ""):lower() == "external"
+        local has_external_handlers = external and #(events.external_event_handlers[id] or {}) > 0
+        if external and not has_external_handlers and not allowed_external[id] then
+            logger.info("Ignored external request for %s.", id)
+        elseif id == "OnShioriReload" then
+            local ok, err = reload()
+            if not ok then result = { text = ("Could not reload scripts; keeping the old ones.\n%s"):format(err), code = 500 } end
+        elseif id then
+            local preprocessor = events.event_preprocessors[id]
+            local procevent = {event}
             if preprocessor then procevent = table.pack(preprocessor(event)) end
-            local handlers = events.event_handlers[event["ID"]] or {}
-            local routine = nil
-            for i, handler in ipairs(handlers) do
-                local remove
-                routine, remove = handler(procevent)
-                if remove then handlers[i] = nil end
-                if routine then 
-                    result = resume_script(routine, event["ID"], procevent, method)
-                    if result.code ~= 204 then break end
-                end
-            end
-            events.event_handlers[event["ID"]] = utils.remove_nils(handlers)
+            local handler_table = has_external_handlers and events.external_event_handlers or events.event_handlers
+            result = dispatch(handler_table, id, procevent, method)
+        end
+        -- Scheduled work runs on a tick that nothing else responded to, while the ghost can talk (Reference3 is 1).
+        if id == "OnSecondChange" and method == "GET" and result.code == 204 and not external
+            and event["Reference3"] ~= "0" then
+            local routine = scheduler.take_due()
+            if routine then result = resume_script(routine, id, {event}, method) end
+        end
+        if id == "OnSecondChange" or id == "OnMinuteChange" then persistent.autosave() end
+        if result.code < 400 then
+            result.headers = response.headers
+            result.code = response.code or result.code
         end
         return result
     end
 
-    require(init_module)
+    local ok, err = xpcall(require, report.build, init_module)
+    if not ok then error(err, 0) end
+    persistent.finish_migrations()
     logger.debug("Initialized scripts.")
 
+    if options.suspended then
+        local function roots()
+            return {
+                { "rsl", loaded_rsl_modules }, { "_G", _G }, { "package.loaded", package.loaded },
+                { "sandbox", sandbox }, { "script_env", script_env }, { "persistent", persistent.data },
+                { "string", getmetatable("") },
+            }
+        end
+        -- The suspended scripts are restored by re-registering their handlers, and the script that is running when
+        -- they are saved belongs to the current request only.
+        local function skip()
+            local skip = {
+                [events.event_handlers] = true, [events.external_event_handlers] = true, [events.suspended] = true,
+            }
+            if script.current then skip[script.current] = true end
+            if interface then skip[interface] = true end
+            return skip
+        end
+        suspended = Suspended(options.suspended, roots, skip)
+        suspended.load()
+    end
+
     return respond
 end
 
@@ -0,0 +1,155 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use rlua::{Context, Function, MultiValue, Table, ToLuaMulti, Value};
+
+use crate::config::{self, Config, Sandbox};
+
+/// Functions from the base library that are safe to give to sandboxed scripts. `load` and `require` are wrapped by
+/// the runtime instead, since they must know the script environment.
+const BASE_FUNCTIONS: &[&str] = &[
+    "assert", "collectgarbage", "error", "getmetatable", "ipairs", "next", "pairs", "pcall", "print", "rawequal",
+    "rawget", "rawlen", "rawset", "select", "setmetatable", "tonumber", "tostring", "type", "xpcall", "_VERSION",
+];
+
+/// Modules that are copied as-is when whitelisted.
+const SAFE_MODULES: &[&str] = &["coroutine", "math", "string", "table", "utf8"];
+
+const SAFE_OS_FUNCTIONS: &[&str] = &["clock", "date", "difftime", "time"];
+
+/// Builds the table that sandboxed scripts see in place of `_G`, containing the safe parts of the base library
+/// and the whitelisted standard library modules. `io` only contains `open`, restricted to `files`, and `type`; `os`
+/// only contains its time functions. Modules that aren't part of the standard library are ignored.
+pub fn create_sandbox<'lua>(ctx: Context<'lua>, files: &FileAccess, config: &Sandbox) -> rlua::Result<Table<'lua>> {
+    let globals = ctx.globals();
+    let sandbox = ctx.create_table()?;
+    for name in BASE_FUNCTIONS {
+        sandbox.set(*name, globals.get::<_, Value>(*name)?)?;
+    }
+
+    for module in &config.modules {
+        let value = match module.as_str() {
+            m if SAFE_MODULES.contains(&m) => copy_table(ctx, globals.get(m)?)?,
+            "os" => {
+                let os: Table = globals.get("os")?;
+                let safe_os = ctx.create_table()?;
+                for name in SAFE_OS_FUNCTIONS {
+                    safe_os.set(*name, os.get::<_, Value>(*name)?)?;
+                }
+                safe_os
+            },
+            "io" => {
+                let io: Table = globals.get("io")?;
+                let safe_io = ctx.create_table()?;
+                safe_io.set("type", io.get::<_, Value>("type")?)?;
+                safe_io.set("open", restricted_open(ctx, io.get("open")?, files.clone())?)?;
+                safe_io
+            },
+            "debug" => copy_table(ctx, globals.get("debug")?)?,
+            _ => continue,
+        };
+        sandbox.set(module.as_str(), value)?;
+    }
+    Ok(sandbox)
+}
+
+/// Removes every way of loading native code from the Lua state: `package.loadlib`, `package.cpath`, and the
+/// searchers that use them.
+pub fn disable_native_libraries(ctx: Context) -> rlua::Result<()> {
+    let package: Table = ctx.globals().get("package")?;
+    package.set("loadlib", Value::Nil)?;
+    package.set("cpath", "")?;
+    // The standard searchers are preload, Lua, C, and all-in-one. Only the first two don't load native code.
+    let searchers: Table = package.get("searchers")?;
+    while searchers.raw_len() > 2 {
+        searchers.raw_set(searchers.raw_len(), Value::Nil)?;
+    }
+    Ok(())
+}
+
+fn copy_table<'lua>(ctx: Context<'lua>, table: Table<'lua>) -> rlua::Result<Table<'lua>> {
+    ctx.create_table_from(table.pairs::<Value, Value>().collect::<rlua::Result<Vec<_>>>()?)
+}
+
+/// Wraps `io.open` so that it only opens files inside the ghost directory, and only opens them for writing inside the
+/// data directory. Relative paths are resolved against the ghost directory, rather than the working directory of the
+/// baseware.
+fn restricted_open<'lua>(ctx: Context<'lua>, open: Function<'lua>, files: FileAccess) -> rlua::Result<Function<'lua>> {
+    let open = ctx.create_registry_value(open)?;
+    ctx.create_function(move |ctx, (path, mode): (String, Option<String>)| {
+        let writing = mode.as_deref().is_some_and(|m| m.contains(['w', 'a', '+']));
+        match files.resolve(Path::new(&path), writing) {
+            Ok(resolved) => {
+                let open: Function = ctx.registry_value(&open)?;
+                open.call::<_, MultiValue>((resolved.to_string_lossy().into_owned(), mode))
+            },
+            Err(e) => (Value::Nil, format!("{}: {}", path, e)).to_lua_multi(ctx),
+        }
+    })
+}
+
+/// The files that sandboxed scripts may open: anything inside the ghost directory for reading, and anything inside
+/// the data directory for writing, except for the files that configure the SHIORI or that it loads data from.
+/// Scripts that could write those could turn the sandbox off, or plant bytecode for Eris to load.
+#[derive(Clone)]
+pub struct FileAccess {
+    root: PathBuf,
+    data: PathBuf,
+    protected: Vec<PathBuf>,
+}
+
+impl FileAccess {
+    /// Creates the data directory, `lua.sandbox.data`, if it doesn't exist.
+    pub fn new(ghost_path: &Path, config: &Config) -> io::Result<Self> {
+        let root = ghost_path.canonicalize()?;
+        fs::create_dir_all(root.join(&config.lua.sandbox.data))?;
+
+        let mut protected = vec![
+            PathBuf::from(config::GHOST_CONFIG),
+            PathBuf::from(config::SCRIPT_CONFIG),
+            PathBuf::from(config::USER_CONFIG),
+            config.lua.persistent.clone(), // Along with its backups and temporary files, whose names start with its own.
+            config.lua.suspended.clone(),
+            config.lua.script_cache.clone(),
+            config.logging.path.clone(),
+        ];
+        protected.extend(config.logging.record.clone());
+        Ok(FileAccess {
+            data: resolve(&root, &config.lua.sandbox.data),
+            protected: protected.iter().map(|p| resolve(&root, p)).collect(),
+            root,
+        })
+    }
+
+    /// Resolves `path` against the ghost directory, failing if it may not be opened.
+    fn resolve(&self, path: &Path, writing: bool) -> io::Result<PathBuf> {
+        let denied = |message| Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+        let resolved = resolve(&self.root, path);
+        if !resolved.starts_with(&self.root) {
+            return denied("Access outside of the ghost directory is not allowed")
+        }
+        if writing {
+            let name = resolved.to_string_lossy();
+            let protected = self.protected.iter().any(|p| name.starts_with(&*p.to_string_lossy()));
+            if !resolved.starts_with(&self.data) || protected {
+                return denied("Writing outside of the data directory is not allowed")
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+/// Resolves `path` against `root`, following symlinks in the existing part of the path. `root` must be canonical.
+fn resolve(root: &Path, path: &Path) -> PathBuf {
+    let mut resolved = if path.is_absolute() { PathBuf::new() } else { root.to_path_buf() };
+    for component in path.components() {
+        match component {
+            Component::ParentDir => { resolved.pop(); },
+            Component::CurDir => (),
+            _ => resolved.push(component),
+        }
+        if let Ok(canonical) = resolved.canonicalize() { resolved = canonical }
+    }
+    resolved
+}
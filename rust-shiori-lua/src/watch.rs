@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use rlua::{UserData, UserDataMethods};
+
+/// How often scripts are checked for changes, at most.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Notices when a Lua script is added, modified or removed. The scripts checked are the `.lua` files directly inside
+/// the script paths, and the files that the script searcher has loaded modules from or looked for them in (see
+/// `WatchedScripts`), so that modules in subdirectories are covered without walking everything under the script
+/// paths, which usually include the ghost's shells. The script paths are only listed again when one of them, or a
+/// script, has changed, since adding, removing or renaming a file changes the modification time of its directory.
+pub struct ScriptWatcher {
+    dirs: Vec<PathBuf>,
+    watched: WatchedScripts,
+    snapshot: Snapshot,
+    last_check: Instant,
+}
+
+/// The modification times of the script paths, and of the scripts in them or watched, which are `None` for the files
+/// that were looked for but don't exist.
+#[derive(Default)]
+struct Snapshot {
+    scripts: BTreeMap<PathBuf, Option<SystemTime>>,
+    dirs: BTreeMap<PathBuf, SystemTime>,
+}
+
+impl ScriptWatcher {
+    pub fn new(dirs: Vec<PathBuf>, watched: WatchedScripts) -> Self {
+        let snapshot = Snapshot::take(&dirs, &watched);
+        ScriptWatcher { dirs, watched, snapshot, last_check: Instant::now() }
+    }
+
+    /// Returns whether a script has changed since the last time this returned true. Returns false without
+    /// checking if it was called less than a second ago.
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < CHECK_INTERVAL { return false }
+        self.last_check = Instant::now();
+
+        // Files the searcher has started watching since the last check are compared to how they were then.
+        for (path, time) in self.watched.files() {
+            self.snapshot.scripts.entry(path).or_insert(time);
+        }
+        if !self.snapshot.is_stale() { return false }
+        let snapshot = Snapshot::take(&self.dirs, &self.watched);
+        let changed = snapshot.scripts != self.snapshot.scripts;
+        self.snapshot = snapshot;
+        changed
+    }
+}
+
+impl Snapshot {
+    fn take(dirs: &[PathBuf], watched: &WatchedScripts) -> Self {
+        let mut snapshot = Snapshot::default();
+        for dir in dirs {
+            if let Some(time) = modified(dir) { snapshot.dirs.insert(dir.clone(), time); }
+            snapshot.list(dir);
+        }
+        for (path, _) in watched.files() {
+            let time = modified(&path);
+            snapshot.scripts.insert(path, time);
+        }
+        snapshot
+    }
+
+    /// Adds the scripts directly inside `dir`.
+    fn list(&mut self, dir: &Path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for path in entries.filter_map(|e| Some(e.ok()?.path())) {
+            if path.extension().is_some_and(|e| e == "lua") && path.is_file() {
+                let time = modified(&path);
+                self.scripts.insert(path, time);
+            }
+        }
+    }
+
+    /// Whether a script or a directory has a different modification time than when this was taken (earlier ones
+    /// included, e.g. when a script is reverted), or no longer exists, or a script that didn't exist now does.
+    fn is_stale(&self) -> bool {
+        self.dirs.iter().any(|(path, &time)| modified(path) != Some(time))
+            || self.scripts.iter().any(|(path, &time)| modified(path) != time)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The files that the script searcher has loaded a module from, or looked for one in, with their modification times
+/// when it did. Shared between the watcher and the runtime, and exposed to the runtime's script searcher.
+#[derive(Clone, Default)]
+pub struct WatchedScripts {
+    files: Arc<Mutex<BTreeMap<PathBuf, Option<SystemTime>>>>,
+}
+
+impl WatchedScripts {
+    fn files(&self) -> Vec<(PathBuf, Option<SystemTime>)> {
+        self.files.lock().map(|files| files.iter().map(|(p, t)| (p.clone(), *t)).collect()).unwrap_or_default()
+    }
+}
+
+impl UserData for WatchedScripts {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Watches the file at `path`, whether or not it exists.
+        methods.add_method("add", |_, this, path: rlua::String| {
+            let path = PathBuf::from(&*String::from_utf8_lossy(path.as_bytes()));
+            if let Ok(mut files) = this.files.lock() {
+                files.entry(path).or_insert_with_key(|path| modified(path));
+            }
+            Ok(())
+        });
+    }
+}
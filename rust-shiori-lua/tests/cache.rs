@@ -0,0 +1,54 @@
+use std::fs;
+
+mod common;
+use common::{Ghost, get};
+
+fn entries(ghost: &Ghost) -> Vec<String> {
+    let mut entries: Vec<_> = fs::read_dir(ghost.file("profile/script-cache")).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    entries.sort();
+    entries
+}
+
+#[test]
+fn scripts_are_cached_under_the_same_names_every_time() {
+    let ghost = Ghost::new(&[("init.lua", "require 'other'"), ("other.lua", "")]);
+    drop(ghost.load());
+    let cached = entries(&ghost);
+    assert_eq!(cached.len(), 2);
+    fs::remove_dir_all(ghost.file("profile/script-cache")).unwrap();
+    drop(ghost.load());
+    assert_eq!(entries(&ghost), cached);
+}
+
+#[test]
+fn rewriting_an_entry_removes_stale_ones() {
+    let ghost = Ghost::new(&[
+        ("init.lua", "require 'other'\nfunction shiori.event.OnTest() shiori.CharacterSet(0) 'old' end"),
+        ("other.lua", ""),
+    ]);
+    drop(ghost.load());
+    let cached = entries(&ghost);
+    ghost.write("profile/script-cache/0123456789abcdef.luac", "rust-shiori-lua 0.0.0, Lua 5.3\ninit.lua\n0 0\n");
+    ghost.write("profile/script-cache/notes.txt", "not an entry");
+
+    // Loading cached scripts leaves the cache as it was.
+    drop(ghost.load());
+    assert_eq!(entries(&ghost).len(), 4);
+
+    // Compiling a script again removes the entries of other versions, and of scripts that are gone.
+    fs::remove_file(ghost.file("other.lua")).unwrap();
+    ghost.write("init.lua", "function shiori.event.OnTest() shiori.CharacterSet(0) 'new' end");
+    assert_eq!(get(&mut ghost.load(), "OnTest", &[]).unwrap(), "\\0new");
+    let remaining = entries(&ghost);
+    assert_eq!(remaining.len(), 2, "{:?}", remaining);
+    assert!(remaining.iter().all(|e| e == "notes.txt" || cached.contains(e)), "{:?}", remaining);
+}
+
+#[test]
+fn scripts_are_cached_in_the_sandbox_too() {
+    let ghost = Ghost::new(&[("rust-shiori.toml", "[lua.sandbox]\nenabled = true\n"), ("init.lua", "")]);
+    drop(ghost.load());
+    assert_eq!(entries(&ghost).len(), 1);
+}
@@ -0,0 +1,86 @@
+//! Ghosts in temporary directories, driven in-process like `shiori-runner` drives them.
+
+#![allow(dead_code)] // Each test crate uses a different part of this.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_shiori::response::Response;
+use rust_shiori_lua::{LoadError, LuaShiori};
+use rust_shiori_runner::{Event, Runner};
+
+static NEXT_GHOST: AtomicUsize = AtomicUsize::new(0);
+
+/// A ghost directory that is removed when dropped.
+pub struct Ghost {
+    pub path: PathBuf,
+}
+
+impl Ghost {
+    /// Creates a ghost with `files`, given as paths relative to the ghost directory and their contents.
+    pub fn new(files: &[(&str, &str)]) -> Self {
+        let name = format!("rust-shiori-lua-{}-{}", std::process::id(), NEXT_GHOST.fetch_add(1, Ordering::Relaxed));
+        let ghost = Ghost { path: std::env::temp_dir().join(name) };
+        let _ = fs::remove_dir_all(&ghost.path);
+        for (path, contents) in files {
+            ghost.write(path, contents);
+        }
+        ghost
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.path.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.path.join(path)).ok()
+    }
+
+    pub fn file(&self, path: &str) -> PathBuf {
+        self.path.join(path)
+    }
+
+    /// Loads the ghost with the runner's clock, so that each tick is a second to its timers.
+    pub fn try_load(&self) -> Result<Runner<LuaShiori>, LoadError> {
+        let mut runner = Runner::<LuaShiori>::load(self.path.clone())?;
+        let clock = runner.clock();
+        runner.shiori().set_clock(move || clock.now());
+        Ok(runner)
+    }
+
+    pub fn load(&self) -> Runner<LuaShiori> {
+        self.try_load().unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl Drop for Ghost {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn path_str(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// The script in `response`, if any.
+pub fn value(response: &Response) -> Option<String> {
+    response.get_field::<String>("Value").map(Result::unwrap)
+}
+
+/// Fires a GET request for `id` and returns the script it responded with.
+pub fn get(runner: &mut Runner<LuaShiori>, id: &str, references: &[&str]) -> Option<String> {
+    value(&runner.fire(&Event::get(id, references)))
+}
+
+/// Fires `seconds` OnSecondChange ticks and returns the scripts they responded with.
+pub fn ticks(runner: &mut Runner<LuaShiori>, seconds: u64) -> Vec<String> {
+    let mut scripts = Vec::new();
+    for _ in 0..seconds {
+        runner.tick(&mut |_, response| scripts.extend(value(response)));
+    }
+    scripts
+}
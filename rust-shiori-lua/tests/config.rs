@@ -0,0 +1,96 @@
+mod common;
+use common::{Ghost, get};
+
+#[test]
+fn sandboxed_scripts_cannot_turn_the_sandbox_off() {
+    let ghost = Ghost::new(&[
+        ("rust-shiori.toml", "[lua.sandbox]\nenabled = true\n"),
+        ("init.lua", r#"
+            local S = shiori.CharacterSet(0)
+            function shiori.event.OnBoot()
+                local file, err = io.open("profile/rust-shiori.toml", "w")
+                S(file and "wrote the user's configuration" or "could not write the user's configuration")
+                local file, err = io.open("profile/script-config.toml", "w")
+                S(file and "wrote the scripts' configuration" or "could not write the scripts' configuration")
+                local ok, err = pcall(shiori.set_config, "lua.sandbox.enabled", false)
+                S(ok and "set lua.sandbox.enabled" or err)
+            end
+            function shiori.event.OnCheck() S(os.execute and "unsandboxed" or "sandboxed") end
+        "#),
+    ]);
+
+    let boot = get(&mut ghost.load(), "OnBoot", &[]).unwrap();
+    assert!(boot.contains("could not write the user's configuration"), "{}", boot);
+    assert!(boot.contains("could not write the scripts' configuration"), "{}", boot);
+    assert!(boot.contains("The sandbox does not allow setting `lua.sandbox.enabled`"), "{}", boot);
+    assert!(get(&mut ghost.load(), "OnCheck", &[]).unwrap().contains("sandboxed"));
+}
+
+/// Responds to `OnRead` with the values of `config.script` named by the references, and sets `script.<Reference0>`
+/// to `Reference1` on `OnSet`.
+const SCRIPT_SETTINGS: &str = r#"
+local S = shiori.CharacterSet(0)
+function shiori.event.OnRead(ev)
+    local values = {}
+    for i = 0, 3 do
+        local value = config.script
+        for key in (ev["Reference" .. i] or ""):gmatch("[^.]+") do value = value and value[key] end
+        if ev["Reference" .. i] then values[#values + 1] = tostring(value) end
+    end
+    S(table.concat(values, " "))
+end
+function shiori.event.OnSet(ev)
+    local ok, err = pcall(shiori.set_config, "script." .. ev.Reference0, ev.Reference1)
+    S(ok and "set" or err)
+end
+"#;
+
+#[test]
+fn script_settings_keep_their_case() {
+    let ghost = Ghost::new(&[
+        ("rust-shiori.toml", "[script]\ntalkInterval = 30\n[script.Nested]\nSomeKey = \"x\"\n"),
+        ("profile/rust-shiori.toml", "[script]\ntalkInterval = 60\n[script.Nested]\nOtherKey = \"y\"\n"),
+        ("init.lua", SCRIPT_SETTINGS),
+    ]);
+    let read = get(&mut ghost.load(), "OnRead", &["talkInterval", "Nested.SomeKey", "Nested.OtherKey", "talkinterval"]);
+    assert_eq!(read.unwrap(), "\\060 x y nil");
+}
+
+#[test]
+fn script_settings_round_trip_through_set_config() {
+    let ghost = Ghost::new(&[("rust-shiori.toml", "[script]\ntalkInterval = 30\n"), ("init.lua", SCRIPT_SETTINGS)]);
+    {
+        let mut runner = ghost.load();
+        assert_eq!(get(&mut runner, "OnSet", &["talkInterval", "45"]).unwrap(), "\\0set");
+        assert_eq!(get(&mut runner, "OnSet", &["debugMode", "on"]).unwrap(), "\\0set");
+    }
+    let read = get(&mut ghost.load(), "OnRead", &["talkInterval", "debugMode", "talkinterval", "debugmode"]);
+    assert_eq!(read.unwrap(), "\\045 on nil nil");
+}
+
+#[test]
+fn environment_variables_override_script_settings_ignoring_case() {
+    std::env::set_var("RUST_SHIORI_SCRIPT__FROMENV", "environment");
+    let ghost = Ghost::new(&[("rust-shiori.toml", "[script]\nfromEnv = \"file\"\n"), ("init.lua", SCRIPT_SETTINGS)]);
+    let read = get(&mut ghost.load(), "OnRead", &["fromEnv", "fromenv"]);
+    assert_eq!(read.unwrap(), "\\0environment nil");
+}
+
+#[test]
+fn environment_variables_that_are_not_configuration_keys_are_ignored() {
+    std::env::set_var("RUST_SHIORI_LUA__NOT_A_KEY", "1");
+    std::env::set_var("RUST_SHIORI_NOT_A_SECTION__KEY", "1");
+    std::env::set_var("RUST_SHIORI_GHOST__NAME", "environment");
+    let init = "function shiori.event.OnName() shiori.CharacterSet(0)(config.ghost.name) end";
+    let ghost = Ghost::new(&[("init.lua", init)]);
+    assert_eq!(get(&mut ghost.load(), "OnName", &[]).unwrap(), "\\0environment");
+}
+
+#[test]
+fn unknown_keys_in_files_are_rejected() {
+    for config in ["[lua]\nnot_a_key = 1\n", "[not_a_section]\nkey = 1\n"] {
+        let ghost = Ghost::new(&[("rust-shiori.toml", config), ("init.lua", "")]);
+        let error = ghost.try_load().err().unwrap().to_string();
+        assert!(error.contains("unknown field `not_a_"), "{}", error);
+    }
+}
@@ -0,0 +1,50 @@
+mod common;
+use common::{Ghost, get};
+
+/// Line numbers matter here: the handlers are defined on lines 2, 6 and 10, and fail on lines 4, 8 and 12.
+const HANDLERS: &str = r#"local S = shiori.CharacterSet(0)
+function shiori.event.OnFail()
+    local missing = nil
+    S(missing.text)
+end
+function shiori.event.OnLoop()
+    local n = 0
+    while true do n = n + 1 end
+end
+function shiori.event.OnPcallLoop()
+    local n = 0
+    while true do pcall(function() n = n + 1 end) end
+end
+"#;
+
+/// A ghost with `HANDLERS`, after `padding` lines of comments. Reporting an error reads the script up to the line it
+/// occured on, so padding makes the runtime do more work after the script has exceeded its budget.
+fn debug_ghost(padding: usize) -> Ghost {
+    let config = "[lua]\ndebug = true\n[lua.limits]\ntime = 0\ninstructions = 100000\n";
+    Ghost::new(&[("rust-shiori.toml", config), ("init.lua", &format!("{}{}", "--\n".repeat(padding), HANDLERS))])
+}
+
+#[test]
+fn errors_name_the_handler_and_line() {
+    let ghost = debug_ghost(0);
+    let balloon = get(&mut ghost.load(), "OnFail", &[]).unwrap();
+    assert!(balloon.starts_with("\\0\\b2\\_qScript error in OnFail\\n\\n[half]"), "{}", balloon);
+    assert!(balloon.contains("init.lua:4: attempt to index a nil value (local 'missing')\\nin the handler for OnFail defined at "), "{}", balloon);
+    assert!(balloon.ends_with("init.lua:2\\e"), "{}", balloon);
+}
+
+#[test]
+fn exceeded_limits_name_the_handler_and_line() {
+    const PADDING: usize = 1000;
+    let ghost = debug_ghost(PADDING);
+    let mut runner = ghost.load();
+    for (id, defined, failed) in [("OnLoop", PADDING + 6, PADDING + 8), ("OnPcallLoop", PADDING + 10, PADDING + 12)] {
+        let balloon = get(&mut runner, id, &[]).unwrap();
+        let (message, handler) = balloon.split_once("\\n\\n[half]").unwrap().1.split_once("\\n").unwrap();
+        assert!(message.contains(&format!("{} exceeded its limit of 100000 instructions", id)), "{}", balloon);
+        assert!(message.contains(&format!("init.lua:{}", failed)), "{}", balloon);
+        assert!(handler.starts_with(&format!("in the handler for {} defined at ", id)), "{}", balloon);
+        assert!(balloon.ends_with(&format!("init.lua:{}\\e", defined)), "{}", balloon);
+        assert!(!balloon.contains("report.lua"), "{}", balloon);
+    }
+}
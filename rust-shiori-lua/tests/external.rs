@@ -0,0 +1,55 @@
+use rust_shiori::internals::handle_request;
+use rust_shiori::response::{Response, ResponseStatus};
+use rust_shiori_lua::LuaShiori;
+use rust_shiori_runner::{Event, Runner};
+
+mod common;
+use common::{Ghost, get, value};
+
+const HANDLERS: &str = r#"
+local S = shiori.CharacterSet(0)
+function shiori.event.OnHello(ev) S("hello " .. ev.security_level) end
+function shiori.event.OnMusicPlay(ev) S("playing " .. ev.Reference0) end
+function shiori.event.OnCommunicate(ev) S "local" end
+function external_event.OnCommunicate(ev) S("external " .. ev.security_level .. " " .. ev.Reference1) end
+"#;
+
+fn ghost(config: &str) -> Ghost {
+    Ghost::new(&[("rust-shiori.toml", config), ("init.lua", HANDLERS)])
+}
+
+/// Fires a GET request for `id` as if it came from outside the machine.
+fn external(runner: &mut Runner<LuaShiori>, id: &str, references: &[&str]) -> Response {
+    let request = Event::get(id, references).to_request().with_field("SecurityLevel", "external");
+    Response::parse(&handle_request(&request.to_wire(), runner.shiori())).unwrap()
+}
+
+#[test]
+fn external_requests_without_handlers_are_ignored() {
+    let ghost = ghost("");
+    let mut runner = ghost.load();
+    let response = external(&mut runner, "OnHello", &[]);
+    assert_eq!(response.status(), ResponseStatus::NoContent);
+    assert!(value(&response).is_none());
+    assert_eq!(get(&mut runner, "OnHello", &[]).unwrap(), "\\0hello local");
+}
+
+#[test]
+fn external_requests_go_to_external_handlers() {
+    let ghost = ghost("");
+    let mut runner = ghost.load();
+    let response = external(&mut runner, "OnCommunicate", &["peer", "hi"]);
+    assert_eq!(value(&response).unwrap(), "\\0external external hi");
+    // Local requests still go to the usual handlers.
+    assert_eq!(get(&mut runner, "OnCommunicate", &["user", "hi"]).unwrap(), "\\0local");
+}
+
+#[test]
+fn configured_external_events_go_to_the_usual_handlers() {
+    let ghost = ghost("[security]\nexternal_events = [\"OnMusicPlay\"]\n");
+    let mut runner = ghost.load();
+    let response = external(&mut runner, "OnMusicPlay", &["song.mp3"]);
+    assert_eq!(value(&response).unwrap(), "\\0playing song.mp3");
+    // Other events are still ignored.
+    assert!(value(&external(&mut runner, "OnHello", &[])).is_none());
+}
@@ -0,0 +1,123 @@
+mod common;
+use common::{Ghost, get};
+
+use rust_shiori_lua::{Interpolator, SourcePosition};
+
+/// Line numbers and columns matter here. Each handler fails on the line after its name, except `OnTrace`, which fails
+/// on line 13 after interpolating on line 12, and `OnAfterLongString`, which fails on line 19.
+const HANDLERS: &str = r#"local S = shiori.CharacterSet(0)
+function shiori.event.OnExpression()
+    S "Hello, ${nobody.name}!"
+end
+function shiori.event.OnAfterExpression()
+    S("${1} ${2}" .. nobody.name)
+end
+function shiori.event.OnLongString()
+    S [[${1} and ${nobody.name}]]
+end
+function shiori.event.OnTrace()
+    local s = "${1} ${2} ${3}"
+    local ok, traceback = xpcall(function() return nobody.name end, require("debug").traceback)
+    S((traceback:gsub("\n", " | ")))
+end
+function shiori.event.OnAfterLongString()
+    local s = [[${1}
+${2} ${3}]]
+    S(nobody.name)
+end
+"#;
+
+fn ghost(config: &str) -> Ghost {
+    Ghost::new(&[("rust-shiori.toml", &format!("[lua]\ndebug = true\n{}", config)), ("init.lua", HANDLERS)])
+}
+
+#[test]
+fn errors_in_interpolated_expressions_name_their_line_and_column() {
+    let mut runner = ghost("").load();
+    for (id, position) in [
+        ("OnExpression", "init.lua:3:15:"),
+        ("OnAfterExpression", "init.lua:6:17:"),
+        ("OnLongString", "init.lua:9:18:"),
+        ("OnAfterLongString", "init.lua:19:"),
+    ] {
+        let balloon = get(&mut runner, id, &[]).unwrap();
+        assert!(balloon.contains(&format!("{} attempt to index a nil value (global 'nobody')", position)), "{}", balloon);
+        assert!(balloon.ends_with(&format!("init.lua:{}\\e", HANDLERS.lines().position(|l| l.contains(id)).unwrap() + 1)),
+            "{}", balloon);
+    }
+}
+
+#[test]
+fn errors_in_cached_scripts_are_mapped_too() {
+    let ghost = ghost("");
+    drop(ghost.load());
+    assert!(ghost.file("profile/script-cache").read_dir().unwrap().next().is_some());
+    let balloon = get(&mut ghost.load(), "OnExpression", &[]).unwrap();
+    assert!(balloon.contains("init.lua:3:15: attempt to index a nil value"), "{}", balloon);
+}
+
+#[test]
+fn tracebacks_point_to_the_source() {
+    let sandboxed = "[lua.sandbox]\nenabled = true\nmodules = [\"debug\", \"string\"]\n";
+    for config in ["", sandboxed] {
+        let traceback = get(&mut ghost(config).load(), "OnTrace", &[]).unwrap();
+        assert!(traceback.contains("init.lua:13: attempt to index a nil value"), "{}", traceback);
+        // The function that failed, then the handler that called it.
+        assert!(traceback.contains("init.lua:13: in function <"), "{}", traceback);
+        assert!(traceback.contains("init.lua:13>"), "{}", traceback);
+        assert!(traceback.contains("init.lua:11>"), "{}", traceback);
+        assert!(!traceback.contains("init.lua:19"), "{}", traceback);
+    }
+}
+
+#[test]
+fn long_strings_keep_their_contents() {
+    let ghost = Ghost::new(&[("init.lua", r#"local S = shiori.CharacterSet(0)
+function shiori.event.OnInterpolated()
+    S [==[
+one ${"x"}
+two [[
+three]] [=[
+four]=] $]==]
+end
+function shiori.event.OnPlain()
+    S "one x\ntwo [[\nthree]] [=[\nfour]=] $"
+end
+function shiori.event.OnBrackets()
+    S [=[${"x"} [[
+y ]=]
+end
+function shiori.event.OnBracketsPlain()
+    S "x [[\ny "
+end
+function shiori.event.OnNested()
+    S [=[${"[[" .. "]]"} ]] ${"]=" .. "]"}]=]
+end
+function shiori.event.OnNestedPlain()
+    S "[[]] ]] ]=]"
+end
+"#)]);
+    let mut runner = ghost.load();
+    let plain = get(&mut runner, "OnPlain", &[]).unwrap();
+    assert_eq!(get(&mut runner, "OnInterpolated", &[]).unwrap(), plain);
+    for id in ["OnBrackets", "OnNested"] {
+        let plain = get(&mut runner, &format!("{}Plain", id), &[]).unwrap();
+        assert_eq!(get(&mut runner, id, &[]).unwrap(), plain);
+    }
+}
+
+#[test]
+fn maps_each_line_to_where_it_starts() {
+    let interpolator = Interpolator::new().unwrap();
+    let line = |line| SourcePosition { line, column: None };
+    let column = |line, column| SourcePosition { line, column: Some(column) };
+
+    let (script, map) = interpolator.process_mapped("a = 'é ${x} b'\nc = [[\n${y}]]\n".as_bytes()).unwrap();
+    assert_eq!(script.iter().filter(|&&c| c == b'\n').count() + 1, map.len());
+    assert_eq!(map, [line(1), column(1, 8), column(1, 12), line(2), line(3), column(3, 1), column(3, 5), line(4)]);
+
+    let source = "a = [=[ [[\n ]] ]=]\n";
+    let (script, map) = interpolator.process_mapped(source.as_bytes()).unwrap();
+    assert_eq!(String::from_utf8(script).unwrap(), "a = ([=[ [[\n ]] ]=])\n");
+    assert_eq!(map, [line(1), line(2), line(3)]);
+}
@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use rust_shiori::response::ResponseStatus;
+use rust_shiori_runner::Event;
+
+mod common;
+use common::{Ghost, get, value};
+
+const HANDLERS: &str = r#"
+local S = shiori.CharacterSet(0)
+function shiori.event.OnHello() S "hello" end
+function shiori.event.OnLoop() while true do end end
+function shiori.event.OnPcall()
+    while true do pcall(function() while true do end end) end
+end
+function shiori.event.OnCatchPcall()
+    local ok, err = pcall(function() while true do end end)
+    S("caught " .. tostring(err))
+end
+function shiori.event.OnCatchResume()
+    local ok, err = coroutine.resume(coroutine.create(function() while true do end end))
+    S("caught " .. tostring(err))
+end
+function shiori.event.OnCatchWrap()
+    local ok, err = pcall(coroutine.wrap(function() while true do end end))
+    S("caught " .. tostring(err))
+end
+function shiori.event.OnAllocate()
+    local t = {}
+    for i = 1, math.huge do t[i] = ("x"):rep(1000) .. i end
+end
+"#;
+
+fn ghost(limits: &str) -> Ghost {
+    Ghost::new(&[("rust-shiori.toml", &format!("[lua.limits]\n{}", limits)), ("init.lua", HANDLERS)])
+}
+
+/// Fires `id`, expecting it to fail, and returns how long it took.
+fn fails(runner: &mut rust_shiori_runner::Runner<rust_shiori_lua::LuaShiori>, id: &str) -> Duration {
+    let start = Instant::now();
+    let response = runner.fire(&Event::get(id, &[]));
+    assert!(response.status() == ResponseStatus::InternalServerError, "{} returned {:?}", id, value(&response));
+    start.elapsed()
+}
+
+#[test]
+fn time_limit_stops_scripts() {
+    let mut runner = ghost("time = 100\n").load();
+    assert!(fails(&mut runner, "OnLoop") < Duration::from_secs(5));
+    assert_eq!(get(&mut runner, "OnHello", &[]).unwrap(), "\\0hello");
+}
+
+#[test]
+fn instruction_limit_stops_scripts() {
+    let mut runner = ghost("time = 0\ninstructions = 100000\n").load();
+    fails(&mut runner, "OnLoop");
+    assert_eq!(get(&mut runner, "OnHello", &[]).unwrap(), "\\0hello");
+}
+
+#[test]
+fn memory_limit_stops_scripts() {
+    let mut runner = ghost("memory = 16777216\n").load();
+    fails(&mut runner, "OnAllocate");
+    assert_eq!(get(&mut runner, "OnHello", &[]).unwrap(), "\\0hello");
+}
+
+#[test]
+fn exceeded_limits_cannot_be_caught() {
+    let mut runner = ghost("time = 0\ninstructions = 100000\n").load();
+    for id in ["OnPcall", "OnCatchPcall", "OnCatchResume", "OnCatchWrap"] {
+        fails(&mut runner, id);
+    }
+    assert_eq!(get(&mut runner, "OnHello", &[]).unwrap(), "\\0hello");
+}
+
+#[test]
+fn each_request_gets_a_fresh_budget() {
+    let mut runner = ghost("time = 0\ninstructions = 100000\n").load();
+    for _ in 0..100 {
+        assert_eq!(get(&mut runner, "OnHello", &[]).unwrap(), "\\0hello");
+    }
+}
@@ -0,0 +1,335 @@
+use std::fs;
+
+mod common;
+use common::{Ghost, get, ticks};
+
+use rust_shiori_lua::LuaShiori;
+use rust_shiori_runner::Runner;
+
+const FORMATS: [&str; 4] = ["eris", "json", "toml", "lua"];
+
+/// Stores one of `fixtures` in `persistent.value` on `OnStore`, and describes `persistent.value` or a fixture in a
+/// canonical form on `OnDump` and `OnFixture`.
+const STORE: &str = r#"
+local S = shiori.CharacterSet(0)
+
+local fixtures = {
+    nested = { a = { b = { c = { "deep" } } }, list = { { x = true }, { y = { 1, 2 } } } },
+    arrays_and_maps = { list = { "a", "b", "c" }, map = { one = 1, two = 2 }, numbers = { 1.5, 2.5 } },
+    numbers = { int = 1, whole = 1.0, half = 0.5, max = math.maxinteger, min = math.mininteger, tiny = 1e-300 },
+    empty = { empty = {}, list = { {} } },
+    strings = { quoted = "\"'\\", lines = "one\ntwo\r\n", utf8 = "こんにちは", control = "\0\1\127x", ["key with spaces"] = "" },
+    non_string_keys = { [1] = "a", [3] = "c", [2.5] = "x", [true] = "yes", [-1] = { [false] = "no" } },
+    special_floats = { nan = 0/0, inf = math.huge, ninf = -math.huge },
+    mixed_array = { 1, "a", { x = true } },
+}
+
+local function dump(value)
+    if type(value) == "table" then
+        local entries = {}
+        for k, v in pairs(value) do entries[#entries + 1] = "[" .. dump(k) .. "]=" .. dump(v) end
+        table.sort(entries)
+        return "{" .. table.concat(entries, ",") .. "}"
+    elseif type(value) == "string" then
+        return "'" .. value:gsub("[^%w ]", function(c) return ("<%d>"):format(c:byte()) end) .. "'"
+    elseif value ~= value then
+        return "nan"
+    elseif math.type(value) == "float" then
+        return ("float %.17g"):format(value)
+    end
+    return type(value) .. " " .. tostring(value)
+end
+
+function shiori.event.OnStore(ev)
+    persistent.value = fixtures[ev.Reference0] or ev.Reference0
+    local ok, saved, err = pcall(persistent.save)
+    S((ok and saved) and "saved" or ("failed: " .. tostring(err or saved)))
+end
+function shiori.event.OnDump() S(dump(persistent.value)) end
+function shiori.event.OnFixture(ev) S(dump(fixtures[ev.Reference0])) end
+"#;
+
+fn ghost(format: &str) -> Ghost {
+    Ghost::new(&[("rust-shiori.toml", &config(format)), ("init.lua", STORE)])
+}
+
+fn config(format: &str) -> String {
+    format!("[lua]\npersistent_format = \"{}\"\npersistent_backups = 3\n", format)
+}
+
+/// Fires `id` and returns the text it responded with.
+fn say(runner: &mut Runner<LuaShiori>, id: &str, references: &[&str]) -> String {
+    get(runner, id, references).unwrap().trim_start_matches("\\0").to_string()
+}
+
+fn store(runner: &mut Runner<LuaShiori>, value: &str) -> String {
+    say(runner, "OnStore", &[value])
+}
+
+fn dump(runner: &mut Runner<LuaShiori>) -> String {
+    say(runner, "OnDump", &[])
+}
+
+/// Saves `fixture` in `format`, then reloads the ghost and checks that it is loaded unchanged.
+fn assert_round_trips(format: &str, fixture: &str) {
+    let ghost = ghost(format);
+    let expected = {
+        let mut runner = ghost.load();
+        let result = store(&mut runner, fixture);
+        assert!(result == "saved", "{} could not save {}: {}", format, fixture, result);
+        say(&mut runner, "OnFixture", &[fixture])
+    };
+    assert_eq!(dump(&mut ghost.load()), expected, "{} did not round-trip {}", format, fixture);
+}
+
+#[test]
+fn every_format_round_trips_tables_strings_and_numbers() {
+    for format in FORMATS {
+        for fixture in ["nested", "arrays_and_maps", "numbers", "empty", "strings"] {
+            assert_round_trips(format, fixture);
+        }
+    }
+}
+
+#[test]
+fn eris_and_lua_round_trip_non_string_keys_and_special_floats() {
+    for format in ["eris", "lua"] {
+        for fixture in ["non_string_keys", "special_floats", "mixed_array"] {
+            assert_round_trips(format, fixture);
+        }
+    }
+}
+
+#[test]
+fn json_and_toml_refuse_to_save_what_they_cannot_represent() {
+    for (format, fixture) in [
+        ("json", "non_string_keys"), ("json", "special_floats"),
+        ("toml", "non_string_keys"), ("toml", "special_floats"), ("toml", "mixed_array"),
+    ] {
+        let ghost = ghost(format);
+        let mut runner = ghost.load();
+        assert!(store(&mut runner, "kept") == "saved");
+        let result = store(&mut runner, fixture);
+        assert!(result.contains("failed: "), "{} saved {}: {}", format, fixture, result);
+        drop(runner);
+        assert!(dump(&mut ghost.load()).contains("kept"), "{} overwrote the save with {}", format, fixture);
+    }
+}
+
+#[test]
+fn saves_are_loaded_in_whichever_format_they_were_saved_in() {
+    for from in FORMATS {
+        for to in FORMATS {
+            let ghost = ghost(from);
+            store(&mut ghost.load(), "nested");
+            ghost.write("rust-shiori.toml", &config(to));
+            let mut runner = ghost.load();
+            assert_eq!(dump(&mut runner), say(&mut runner, "OnFixture", &["nested"]), "{} to {}", from, to);
+
+            // The next save converts the data.
+            assert!(store(&mut runner, "converted") == "saved");
+            let saved = fs::read(ghost.file("profile/persistent.dat")).unwrap();
+            let text = String::from_utf8_lossy(&saved);
+            match to {
+                "eris" => assert!(saved.starts_with(b"ERIS")),
+                "json" => assert!(text.starts_with('{'), "{}", text),
+                "toml" => assert!(text.starts_with("schema_version"), "{}", text),
+                _ => assert!(text.starts_with("return {"), "{}", text),
+            }
+        }
+    }
+}
+
+#[test]
+fn hand_written_saves_are_detected() {
+    for save in [
+        "-- Edited by hand.\nreturn { value = 'by hand' }\n",
+        "return{ value = 'by hand' }",
+        "\n  { \"value\": \"by hand\" }\n",
+        "# Edited by hand.\nvalue = \"by hand\"\n",
+        "return_value = 1\nvalue = \"by hand\"\n",
+        "return = 1\nvalue = \"by hand\"\n",
+    ] {
+        let ghost = ghost("eris");
+        ghost.write("profile/persistent.dat", save);
+        assert_eq!(dump(&mut ghost.load()), "'by hand'", "{:?} was not loaded", save);
+    }
+}
+
+#[test]
+fn loading_falls_back_to_the_newest_valid_backup() {
+    for format in FORMATS {
+        let ghost = ghost(format);
+        {
+            let mut runner = ghost.load();
+            for value in ["first", "second", "third", "fourth"] {
+                assert!(store(&mut runner, value) == "saved");
+            }
+        }
+        for backup in 1..=3 {
+            assert!(ghost.file(&format!("profile/persistent.dat.{}", backup)).is_file());
+        }
+        assert_eq!(dump(&mut ghost.load()), "'fourth'");
+
+        ghost.write("profile/persistent.dat", "{ truncated");
+        assert_eq!(dump(&mut ghost.load()), "'third'", "{}", format);
+
+        // Each unreadable file is skipped in turn.
+        ghost.write("profile/persistent.dat", "ERIS");
+        ghost.write("profile/persistent.dat.1", "return {");
+        assert_eq!(dump(&mut ghost.load()), "'second'", "{}", format);
+
+        ghost.write("profile/persistent.dat.2", "value = ");
+        assert_eq!(dump(&mut ghost.load()), "'first'", "{}", format);
+
+        // With nothing left to load, the ghost starts over.
+        ghost.write("profile/persistent.dat.3", "value = = 1");
+        let mut runner = ghost.load();
+        assert_eq!(dump(&mut runner), "nil nil");
+        assert!(store(&mut runner, "fresh") == "saved");
+    }
+}
+
+/// Keeps `persistent.value` without saving it on `OnSet`, responds with it on `OnGet`, and reports what
+/// `persistent.save()` returned on `OnSave`.
+const SET: &str = r#"
+local S = shiori.CharacterSet(0)
+function shiori.event.OnSet(ev) persistent.value = ev.Reference0 end
+function shiori.event.OnGet() S(tostring(persistent.value)) end
+function shiori.event.OnSave()
+    local ok, err = persistent.save()
+    S(tostring(ok) .. " " .. tostring(err))
+end
+"#;
+
+#[test]
+fn data_is_autosaved_every_interval() {
+    let ghost = Ghost::new(&[("rust-shiori.toml", "[lua]\nautosave = 3\n"), ("init.lua", SET)]);
+    let mut runner = ghost.load();
+    get(&mut runner, "OnSet", &["first"]);
+    // The first interval counts from loading, which may have been up to a second before the runner's clock started.
+    for _ in 0..3 {
+        if ghost.file("profile/persistent.dat").exists() { break }
+        ticks(&mut runner, 1);
+    }
+    let saved = fs::read(ghost.file("profile/persistent.dat")).unwrap();
+
+    get(&mut runner, "OnSet", &["second"]);
+    ticks(&mut runner, 2);
+    assert_eq!(fs::read(ghost.file("profile/persistent.dat")).unwrap(), saved);
+    ticks(&mut runner, 1);
+    assert_ne!(fs::read(ghost.file("profile/persistent.dat")).unwrap(), saved);
+    drop(runner);
+    assert_eq!(say(&mut ghost.load(), "OnGet", &[]), "second");
+}
+
+#[test]
+fn autosave_can_be_turned_off() {
+    let ghost = Ghost::new(&[("rust-shiori.toml", "[lua]\nautosave = 0\n"), ("init.lua", SET)]);
+    let mut runner = ghost.load();
+    get(&mut runner, "OnSet", &["value"]);
+    ticks(&mut runner, 120);
+    assert!(!ghost.file("profile/persistent.dat").exists());
+}
+
+#[test]
+fn unchanged_data_is_not_saved_again() {
+    let ghost = Ghost::new(&[("rust-shiori.toml", "[lua]\nautosave = 1\n"), ("init.lua", SET)]);
+    let mut runner = ghost.load();
+    get(&mut runner, "OnSet", &["value"]);
+    assert_eq!(say(&mut runner, "OnSave", &[]), "true nil");
+    // Every save that is written moves the previous one to a backup, so there would be one by now.
+    assert_eq!(say(&mut runner, "OnSave", &[]), "true nil");
+    ticks(&mut runner, 3);
+    assert!(!ghost.file("profile/persistent.dat.1").exists());
+    drop(runner);
+
+    // Nor is data that is unchanged since it was loaded.
+    let mut runner = ghost.load();
+    assert_eq!(say(&mut runner, "OnSave", &[]), "true nil");
+    ticks(&mut runner, 3);
+    assert!(!ghost.file("profile/persistent.dat.1").exists());
+
+    get(&mut runner, "OnSet", &["changed"]);
+    assert_eq!(say(&mut runner, "OnSave", &[]), "true nil");
+    assert!(ghost.file("profile/persistent.dat.1").is_file());
+}
+
+#[test]
+fn failed_saves_return_false_and_a_message() {
+    let config = "[lua]\npersistent = \"./blocked/persistent.dat\"\n";
+    let ghost = Ghost::new(&[("rust-shiori.toml", config), ("init.lua", SET), ("blocked", "a file, not a directory")]);
+    let mut runner = ghost.load();
+    get(&mut runner, "OnSet", &["value"]);
+    let result = say(&mut runner, "OnSave", &[]);
+    assert!(result.starts_with("false ") && result.contains("persistent.dat"), "{}", result);
+    // The ghost keeps running, and tries again next time.
+    assert_eq!(say(&mut runner, "OnSave", &[]), result);
+}
+
+/// A ghost that registers `migrations` before the handlers in `SET`, and saves in the Lua format, starting from `save`.
+fn migrating_ghost(migrations: &str, save: Option<&str>) -> Ghost {
+    let init = format!("{}\n{}", migrations, SET);
+    let mut files = vec![("rust-shiori.toml", "[lua]\npersistent_format = \"lua\"\n"), ("init.lua", init.as_str())];
+    files.extend(save.map(|save| ("profile/persistent.dat", save)));
+    Ghost::new(&files)
+}
+
+/// Migrates `count` to `visits` and then to `stats.visits`, as in the docs, and responds with it on `OnVisits`.
+const MIGRATIONS: &str = r#"
+persistent.migrate(0, 1, function(data) data.visits, data.count = data.count, nil end)
+persistent.migrate(1, 2, function(data) return { stats = { visits = data.visits }, value = data.value } end)
+function shiori.event.OnVisits() shiori.CharacterSet(0)(tostring(persistent.stats and persistent.stats.visits)) end
+"#;
+
+fn saved_version(ghost: &Ghost) -> String {
+    let save = ghost.read("profile/persistent.dat").unwrap();
+    let start = save.find("schema_version = ").unwrap() + "schema_version = ".len();
+    save[start..].chars().take_while(char::is_ascii_digit).collect()
+}
+
+#[test]
+fn migrations_chain_into_each_other() {
+    let ghost = migrating_ghost(MIGRATIONS, Some("return { count = 5 }"));
+    let mut runner = ghost.load();
+    assert_eq!(say(&mut runner, "OnVisits", &[]), "5");
+    get(&mut runner, "OnSet", &["value"]);
+    assert_eq!(say(&mut runner, "OnSave", &[]), "true nil");
+    assert_eq!(saved_version(&ghost), "2");
+    drop(runner);
+
+    // Data saved at the latest version is left alone.
+    let mut runner = ghost.load();
+    assert_eq!(say(&mut runner, "OnVisits", &[]), "5");
+    assert_eq!(say(&mut runner, "OnGet", &[]), "value");
+}
+
+#[test]
+fn new_data_starts_at_the_latest_version() {
+    let ghost = migrating_ghost(MIGRATIONS, None);
+    let mut runner = ghost.load();
+    assert_eq!(say(&mut runner, "OnVisits", &[]), "nil");
+    get(&mut runner, "OnSet", &["value"]);
+    assert_eq!(say(&mut runner, "OnSave", &[]), "true nil");
+    assert_eq!(saved_version(&ghost), "2");
+}
+
+#[test]
+fn failed_migrations_restore_the_data_and_disable_saving() {
+    let migrations = r#"
+persistent.migrate(0, 1, function(data)
+    data.count, data.list[1] = nil, "changed"
+    assert(false, "broken migration")
+end)
+function shiori.event.OnCount() shiori.CharacterSet(0)(persistent.count .. " " .. persistent.list[1]) end
+"#;
+    let save = "return { count = 5, list = { \"original\" } }";
+    let ghost = migrating_ghost(migrations, Some(save));
+    let mut runner = ghost.load();
+    assert_eq!(say(&mut runner, "OnCount", &[]), "5 original");
+    get(&mut runner, "OnSet", &["value"]);
+    assert_eq!(say(&mut runner, "OnSave", &[]), "false saving is disabled because a migration failed");
+    get(&mut runner, "OnDestroy", &[]);
+    drop(runner);
+    assert_eq!(ghost.read("profile/persistent.dat").unwrap(), save);
+}
@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+mod common;
+use common::{Ghost, get};
+
+const CONFIG: &str = "[lua]\nreload_on_change = true\n";
+
+fn greeting(text: &str) -> String {
+    format!("shiori.event.OnBoot = function() shiori.CharacterSet(0) '{}' end\n", text)
+}
+
+/// Waits out the watcher's check interval.
+fn wait_for_watcher() {
+    thread::sleep(Duration::from_millis(1100));
+}
+
+#[test]
+fn reverted_script_is_reloaded() {
+    let ghost = Ghost::new(&[("rust-shiori.toml", CONFIG), ("init.lua", &greeting("new"))]);
+    let mut runner = ghost.load();
+    assert!(get(&mut runner, "OnBoot", &[]).unwrap().contains("new"));
+
+    // Restore an older version, with its older modification time.
+    ghost.write("init.lua", &greeting("old"));
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+    File::options().write(true).open(ghost.file("init.lua")).unwrap().set_modified(an_hour_ago).unwrap();
+    wait_for_watcher();
+    assert!(get(&mut runner, "OnBoot", &[]).unwrap().contains("old"));
+}
+
+#[test]
+fn new_script_in_a_new_directory_is_loaded() {
+    let ghost = Ghost::new(&[
+        ("rust-shiori.toml", CONFIG),
+        ("init.lua", "shiori.event.OnBoot = function() require('events.boot') end\n"),
+    ]);
+    let mut runner = ghost.load();
+    assert!(get(&mut runner, "OnBoot", &[]).is_none());
+
+    ghost.write("events/boot.lua", "shiori.CharacterSet(0) 'from a new file'\n");
+    wait_for_watcher();
+    assert!(get(&mut runner, "OnBoot", &[]).unwrap().contains("from a new file"));
+}
+
+#[test]
+fn other_files_do_not_trigger_a_reload() {
+    let ghost = Ghost::new(&[
+        ("rust-shiori.toml", CONFIG),
+        ("init.lua", "local n = 0\nshiori.event.OnBoot = function() n = n + 1; shiori.CharacterSet(0)(n) end\n"),
+    ]);
+    let mut runner = ghost.load();
+    assert!(get(&mut runner, "OnBoot", &[]).unwrap().contains('1'));
+
+    ghost.write("shell/master/descript.txt", "charset,UTF-8\n");
+    ghost.write("notes.txt", "not a script\n");
+    wait_for_watcher();
+    assert!(get(&mut runner, "OnBoot", &[]).unwrap().contains('2'));
+}
+
+#[test]
+fn modules_in_subdirectories_are_watched() {
+    let ghost = Ghost::new(&[
+        ("rust-shiori.toml", CONFIG),
+        ("init.lua", "shiori.event.OnBoot = function() shiori.CharacterSet(0)(require('events.boot')) end\n"),
+        ("events/boot.lua", "return 'old'\n"),
+    ]);
+    let mut runner = ghost.load();
+    assert!(get(&mut runner, "OnBoot", &[]).unwrap().contains("old"));
+
+    ghost.write("events/boot.lua", "return 'new'\n");
+    wait_for_watcher();
+    assert!(get(&mut runner, "OnBoot", &[]).unwrap().contains("new"));
+}
+
+#[test]
+fn scripts_that_are_not_modules_of_the_ghost_are_not_watched() {
+    let ghost = Ghost::new(&[
+        ("rust-shiori.toml", CONFIG),
+        ("init.lua", "local n = 0\nshiori.event.OnBoot = function() n = n + 1; shiori.CharacterSet(0)(n) end\n"),
+        ("shell/master/tool.lua", "-- Not loaded by the ghost.\n"),
+    ]);
+    let mut runner = ghost.load();
+    assert!(get(&mut runner, "OnBoot", &[]).unwrap().contains('1'));
+
+    ghost.write("shell/master/tool.lua", "-- Changed.\n");
+    ghost.write("shell/master/other.lua", "-- Added.\n");
+    wait_for_watcher();
+    assert!(get(&mut runner, "OnBoot", &[]).unwrap().contains('2'));
+}
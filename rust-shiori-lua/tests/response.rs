@@ -0,0 +1,105 @@
+use rust_shiori::response::{Response, ResponseStatus};
+use rust_shiori_lua::LuaShiori;
+use rust_shiori_runner::{Event, Runner};
+
+mod common;
+use common::{Ghost, get, value};
+
+const HANDLERS: &str = r#"
+local S = shiori.CharacterSet(0)
+function shiori.event.OnMarker() S "marked" shiori.response.header("Marker", 3) end
+function shiori.event.OnPassThru() shiori.response.passthru("Answer", "yes") end
+function shiori.event.OnRemoved()
+    shiori.response.header("Marker", "set")
+    shiori.response.header("Marker", nil)
+end
+function shiori.event.OnStatus(ev) shiori.response.status(tonumber(ev.Reference0)) end
+function shiori.event.OnFailure() shiori.response.header("Marker", "lost") assert(false) end
+-- Line breaks can't be sent in a request, so they are sent as `\r` and `\n`, and echoed back as spaces.
+local function unescape(s) return (s:gsub("\\r", "\r"):gsub("\\n", "\n")) end
+function shiori.event.OnHeader(ev)
+    local ok, err = pcall(shiori.response.header, unescape(ev.Reference0), unescape(ev.Reference1))
+    S((ok and "set" or err):gsub("[\r\n]", " "))
+end
+"#;
+
+fn load() -> (Ghost, Runner<LuaShiori>) {
+    let ghost = Ghost::new(&[("init.lua", HANDLERS)]);
+    let runner = ghost.load();
+    (ghost, runner)
+}
+
+fn field(response: &Response, name: &str) -> Option<String> {
+    response.get_field::<String>(name).map(Result::unwrap)
+}
+
+#[test]
+fn headers_are_added_to_the_response() {
+    let (_ghost, mut runner) = load();
+    let response = runner.fire(&Event::get("OnMarker", &[]));
+    assert_eq!(value(&response).unwrap(), "\\0marked");
+    assert_eq!(field(&response, "Marker").unwrap(), "3");
+
+    let response = runner.fire(&Event::get("OnPassThru", &[]));
+    assert_eq!(field(&response, "X-SSTP-PassThru-Answer").unwrap(), "yes");
+
+    let response = runner.fire(&Event::notify("OnPassThru", &[]));
+    assert_eq!(response.status(), ResponseStatus::NoContent);
+    assert_eq!(field(&response, "X-SSTP-PassThru-Answer").unwrap(), "yes");
+}
+
+#[test]
+fn headers_only_apply_to_their_own_response() {
+    let (_ghost, mut runner) = load();
+    assert!(field(&runner.fire(&Event::get("OnRemoved", &[])), "Marker").is_none());
+    runner.fire(&Event::get("OnMarker", &[]));
+    assert!(field(&runner.fire(&Event::get("OnPassThru", &[])), "Marker").is_none());
+
+    let response = runner.fire(&Event::get("OnFailure", &[]));
+    assert_eq!(response.status(), ResponseStatus::InternalServerError);
+    assert!(field(&response, "Marker").is_none());
+}
+
+#[test]
+fn status_replaces_the_default_one() {
+    let (_ghost, mut runner) = load();
+    let cases = [
+        ("200", ResponseStatus::OK), ("204", ResponseStatus::NoContent), ("311", ResponseStatus::NotEnough),
+        ("312", ResponseStatus::Advice), ("400", ResponseStatus::BadRequest),
+        ("500", ResponseStatus::InternalServerError),
+    ];
+    for (code, status) in cases {
+        assert_eq!(runner.fire(&Event::get("OnStatus", &[code])).status(), status, "{}", code);
+    }
+    assert_eq!(runner.fire(&Event::notify("OnStatus", &["200"])).status(), ResponseStatus::OK);
+
+    for code in ["404", "0", "two hundred"] {
+        let status = runner.fire(&Event::get("OnStatus", &[code])).status();
+        assert_eq!(status, ResponseStatus::InternalServerError, "{}", code);
+    }
+}
+
+#[test]
+fn reserved_headers_are_rejected() {
+    let (_ghost, mut runner) = load();
+    for name in ["Charset", "Value"] {
+        let error = get(&mut runner, "OnHeader", &[name, "x"]).unwrap();
+        assert!(error.contains(&format!("The {} header cannot be set by scripts!", name)), "{}", error);
+    }
+    let response = runner.fire(&Event::get("OnMarker", &[]));
+    assert_eq!(field(&response, "Charset").unwrap(), "UTF-8");
+}
+
+#[test]
+fn line_breaks_are_rejected() {
+    let (_ghost, mut runner) = load();
+    for value in [r"a\rb", r"a\nb", r"a\r\nForged: header"] {
+        let error = get(&mut runner, "OnHeader", &["Marker", value]).unwrap();
+        assert!(error.contains("The value of response header Marker contains a line break!"), "{}", error);
+    }
+    for name in [r"Mar\nker", r"Mar\rker", "Marker: 1", ""] {
+        let error = get(&mut runner, "OnHeader", &[name, "x"]).unwrap();
+        assert!(error.contains("Invalid response header name"), "{}", error);
+    }
+    assert_eq!(get(&mut runner, "OnHeader", &["Marker", "fine"]).unwrap(), "\\0set");
+}
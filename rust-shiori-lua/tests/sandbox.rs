@@ -0,0 +1,105 @@
+mod common;
+use common::{Ghost, get, path_str};
+
+use rust_shiori_lua::LuaShiori;
+use rust_shiori_runner::Runner;
+
+const CONFIG: &str = "[lua.sandbox]\nenabled = true\n";
+
+/// Responds to `OnOpen` with whether `io.open(Reference0, Reference1)` succeeded.
+const OPENER: &str = r#"
+function shiori.event.OnOpen(ev)
+    local file, err = io.open(ev.Reference0, ev.Reference1)
+    if file then file:close() end
+    shiori.CharacterSet(0)(file and "opened" or err)
+end
+"#;
+
+fn opens(runner: &mut Runner<LuaShiori>, path: &str, mode: &str) -> bool {
+    let result = get(runner, "OnOpen", &[path, mode]).unwrap();
+    assert!(result.contains("opened") || result.contains("not allowed"), "{}", result);
+    result.contains("opened")
+}
+
+fn sandboxed_ghost(config: &str) -> Ghost {
+    Ghost::new(&[("rust-shiori.toml", config), ("init.lua", OPENER), ("profile/rust-shiori.toml", "")])
+}
+
+#[test]
+fn scripts_can_read_the_ghost_directory() {
+    let ghost = sandboxed_ghost(CONFIG);
+    let mut runner = ghost.load();
+    assert!(opens(&mut runner, "init.lua", "r"));
+    assert!(opens(&mut runner, "./profile/../rust-shiori.toml", "rb"));
+    assert!(opens(&mut runner, &path_str(&ghost.file("init.lua")), "r"));
+}
+
+#[test]
+fn scripts_cannot_leave_the_ghost_directory() {
+    let ghost = sandboxed_ghost(CONFIG);
+    let outside = Ghost::new(&[("secret.txt", "")]);
+    let mut runner = ghost.load();
+    let relative = format!("../{}/secret.txt", outside.path.file_name().unwrap().to_string_lossy());
+    assert!(!opens(&mut runner, &relative, "r"));
+    assert!(!opens(&mut runner, &format!("profile/data/../../{}", relative), "r"));
+    assert!(!opens(&mut runner, &format!("missing/../../{}", relative), "r"));
+    assert!(!opens(&mut runner, &path_str(&outside.file("secret.txt")), "r"));
+}
+
+#[test]
+fn scripts_can_only_write_to_the_data_directory() {
+    let ghost = sandboxed_ghost(CONFIG);
+    let mut runner = ghost.load();
+    assert!(opens(&mut runner, "profile/data/notes.txt", "w"));
+    assert!(ghost.file("profile/data/notes.txt").exists());
+    assert!(opens(&mut runner, "profile/data/notes.txt", "a+"));
+    assert!(opens(&mut runner, "./profile/data/../data/notes.txt", "r+"));
+
+    assert!(!opens(&mut runner, "notes.txt", "w"));
+    assert!(!opens(&mut runner, "init.lua", "a"));
+    assert!(!opens(&mut runner, "profile/data/../notes.txt", "w"));
+    assert!(!opens(&mut runner, &path_str(&ghost.file("notes.txt")), "w"));
+}
+
+#[test]
+fn configuration_and_persistent_files_are_never_writable() {
+    // Even with a data directory that contains them.
+    let ghost = sandboxed_ghost(&format!("{}data = \"./\"\n", CONFIG));
+    let mut runner = ghost.load();
+    assert!(opens(&mut runner, "notes.txt", "w"));
+
+    for file in [
+        "rust-shiori.toml",
+        "profile/rust-shiori.toml",
+        "profile/script-config.toml",
+        "profile/persistent.dat",
+        "profile/persistent.dat.1",
+        "profile/persistent.dat.tmp",
+        "profile/suspended.dat",
+        "profile/script-cache/0123456789abcdef.luac",
+        "rust-shiori.log",
+    ] {
+        assert!(!opens(&mut runner, file, "w"), "{} is writable", file);
+        assert!(!opens(&mut runner, file, "r+"), "{} is writable", file);
+    }
+    assert!(opens(&mut runner, "profile/rust-shiori.toml", "r"));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_are_followed_before_checking() {
+    use std::os::unix::fs::symlink;
+
+    let ghost = sandboxed_ghost(CONFIG);
+    let outside = Ghost::new(&[("secret.txt", "")]);
+    let mut runner = ghost.load();
+    symlink(&outside.path, ghost.file("profile/data/outside")).unwrap();
+    symlink(ghost.file("profile/rust-shiori.toml"), ghost.file("profile/data/config.toml")).unwrap();
+    symlink(ghost.file("profile/data"), ghost.file("data")).unwrap();
+
+    assert!(!opens(&mut runner, "profile/data/outside/secret.txt", "r"));
+    assert!(!opens(&mut runner, "profile/data/outside/new.txt", "w"));
+    assert!(!opens(&mut runner, "profile/data/config.toml", "w"));
+    assert!(opens(&mut runner, "profile/data/config.toml", "r"));
+    assert!(opens(&mut runner, "data/notes.txt", "w"));
+}
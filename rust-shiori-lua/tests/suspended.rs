@@ -0,0 +1,84 @@
+mod common;
+use common::{Ghost, get};
+
+use rust_shiori_lua::LuaShiori;
+use rust_shiori_runner::Runner;
+
+const CONFIG: &str = "[lua]\npersist_suspended = true\n";
+
+/// Suspends a script until `OnResume`, then has it count up `state.y.n` of the module `state`.
+const INIT: &str = r#"
+local state = require 'state'
+local S = shiori.CharacterSet(0)
+
+function shiori.event.OnWait()
+    local counter = state.y
+    S "waiting"
+    shiori.resume_on_event("OnResume")
+    counter.n = counter.n + 1
+    S("resumed " .. counter.n)
+end
+
+function shiori.event.OnCount() S("count " .. state.y.n) end
+"#;
+
+fn ghost(state: &str) -> Ghost {
+    Ghost::new(&[("rust-shiori.toml", CONFIG), ("init.lua", INIT), ("state.lua", state)])
+}
+
+/// Sends `OnDestroy`, as the baseware does before unloading, and unloads the ghost.
+fn close(mut runner: Runner<LuaShiori>) {
+    get(&mut runner, "OnDestroy", &[]);
+    drop(runner);
+}
+
+#[test]
+fn suspended_scripts_resume_in_the_next_session() {
+    let ghost = ghost("return { y = { n = 0 } }");
+    let mut runner = ghost.load();
+    assert_eq!(get(&mut runner, "OnWait", &[]).unwrap(), "\\0waiting");
+    close(runner);
+    assert!(ghost.file("profile/suspended.dat").is_file());
+
+    let mut runner = ghost.load();
+    assert_eq!(get(&mut runner, "OnResume", &[]).unwrap(), "\\0resumed 1");
+    // It only resumes once.
+    assert!(get(&mut runner, "OnResume", &[]).is_none());
+    close(runner);
+
+    let mut runner = ghost.load();
+    assert!(get(&mut runner, "OnResume", &[]).is_none());
+}
+
+#[test]
+fn values_of_loaded_modules_are_restored_by_path() {
+    let ghost = ghost("return { y = { n = 0 } }");
+    let mut runner = ghost.load();
+    get(&mut runner, "OnWait", &[]);
+    close(runner);
+
+    // The restored script refers to `package.loaded.state.y` as loaded in the new session, not to a copy of the old.
+    ghost.write("state.lua", "return { y = { n = 10 } }");
+    let mut runner = ghost.load();
+    assert_eq!(get(&mut runner, "OnResume", &[]).unwrap(), "\\0resumed 11");
+    assert_eq!(get(&mut runner, "OnCount", &[]).unwrap(), "\\0count 11");
+}
+
+#[test]
+fn suspended_scripts_are_dropped_when_a_path_no_longer_exists() {
+    let ghost = ghost("return { y = { n = 0 } }");
+    let mut runner = ghost.load();
+    get(&mut runner, "OnWait", &[]);
+    close(runner);
+
+    ghost.write("state.lua", "return { z = { n = 0 } }");
+    let mut runner = ghost.load();
+    assert!(get(&mut runner, "OnResume", &[]).is_none());
+    close(runner);
+
+    // It is gone for good, even once the path exists again.
+    ghost.write("state.lua", "return { y = { n = 0 } }");
+    let mut runner = ghost.load();
+    assert!(get(&mut runner, "OnResume", &[]).is_none());
+    assert_eq!(get(&mut runner, "OnCount", &[]).unwrap(), "\\0count 0");
+}
@@ -0,0 +1,81 @@
+use rust_shiori::request::Method;
+use rust_shiori::response::{Response, ResponseStatus};
+use rust_shiori_lua::LuaShiori;
+use rust_shiori_runner::{Event, Runner};
+
+mod common;
+use common::{Ghost, value};
+
+const HANDLERS: &str = r#"
+local S = shiori.CharacterSet(0)
+function shiori.event.OnTeach(ev)
+    if ev.word == "history" then
+        S(ev.id .. " " .. #ev.history .. ": " .. table.concat(ev.history, ", "))
+    elseif ev.word == "advise" then
+        S "Are you sure?"
+        local answer, answer_ev = shiori.teach.advise()
+        S("advised " .. answer .. " after " .. table.concat(answer_ev.history, ", "))
+    else
+        S("What does " .. ev.word .. " mean?")
+        local meaning = shiori.teach.ask()
+        S(ev.word .. " means " .. meaning)
+    end
+end
+"#;
+
+fn load() -> (Ghost, Runner<LuaShiori>) {
+    let ghost = Ghost::new(&[("init.lua", HANDLERS)]);
+    let runner = ghost.load();
+    (ghost, runner)
+}
+
+/// Sends a TEACH request for `word`, with the words taught before it in the same conversation, most recent first.
+fn teach(runner: &mut Runner<LuaShiori>, word: &str, history: &[&str]) -> Response {
+    let references = std::iter::once(word).chain(history.iter().copied()).map(str::to_string).collect();
+    runner.fire(&Event { method: Method::Teach, id: "OnTeach".to_string(), references })
+}
+
+#[test]
+fn teach_requests_are_handled_as_on_teach() {
+    let (_ghost, mut runner) = load();
+    let response = teach(&mut runner, "history", &[]);
+    assert_eq!(response.status(), ResponseStatus::OK);
+    assert_eq!(value(&response).unwrap(), "\\0OnTeach 0: ");
+    let response = teach(&mut runner, "history", &["second", "first"]);
+    assert_eq!(value(&response).unwrap(), "\\0OnTeach 2: second, first");
+}
+
+#[test]
+fn ask_waits_for_another_word_with_311() {
+    let (_ghost, mut runner) = load();
+    let response = teach(&mut runner, "apple", &[]);
+    assert_eq!(response.status(), ResponseStatus::NotEnough);
+    assert_eq!(value(&response).unwrap(), "\\0What does apple mean?");
+
+    let response = teach(&mut runner, "fruit", &["apple"]);
+    assert_eq!(response.status(), ResponseStatus::OK);
+    assert_eq!(value(&response).unwrap(), "\\0apple means fruit");
+}
+
+#[test]
+fn advise_waits_for_another_word_with_312() {
+    let (_ghost, mut runner) = load();
+    let response = teach(&mut runner, "advise", &[]);
+    assert_eq!(response.status(), ResponseStatus::Advice);
+    assert_eq!(value(&response).unwrap(), "\\0Are you sure?");
+
+    let response = teach(&mut runner, "yes", &["advise"]);
+    assert_eq!(response.status(), ResponseStatus::OK);
+    assert_eq!(value(&response).unwrap(), "\\0advised yes after advise");
+}
+
+#[test]
+fn only_answers_resume_a_question() {
+    let (_ghost, mut runner) = load();
+    teach(&mut runner, "apple", &[]);
+    // A word with no history starts a new conversation, rather than answering the question.
+    let response = teach(&mut runner, "history", &[]);
+    assert_eq!(value(&response).unwrap(), "\\0OnTeach 0: ");
+    let response = teach(&mut runner, "fruit", &["apple"]);
+    assert_eq!(value(&response).unwrap(), "\\0apple means fruit");
+}
@@ -0,0 +1,130 @@
+mod common;
+use common::{Ghost, get, ticks};
+
+use rust_shiori_lua::LuaShiori;
+use rust_shiori_runner::{Event, Runner};
+
+/// Ghosts are loaded with the runner's clock, which moves forward a second with every tick, so these count time in
+/// ticks. Callbacks say what called them and how many times they have been called.
+fn ghost(init: &str) -> Ghost {
+    Ghost::new(&[("rust-shiori.toml", "[lua]\ndebug = true\n"), ("init.lua", &format!("{}\n{}", r#"
+local S = shiori.CharacterSet(0)
+local calls = {}
+function say(name)
+    return function()
+        calls[name] = (calls[name] or 0) + 1
+        S(name .. " " .. calls[name])
+    end
+end
+"#, init))])
+}
+
+#[test]
+fn one_shot_timers_run_once() {
+    let mut runner = ghost(r#"shiori.after(3, say("after"))"#).load();
+    assert!(ticks(&mut runner, 2).is_empty());
+    assert_eq!(ticks(&mut runner, 1), ["\\0after 1"]);
+    assert!(ticks(&mut runner, 10).is_empty());
+}
+
+#[test]
+fn repeating_timers_run_every_interval() {
+    let mut runner = ghost(r#"shiori.every(2, say("every"))"#).load();
+    assert!(ticks(&mut runner, 1).is_empty());
+    assert_eq!(ticks(&mut runner, 1), ["\\0every 1"]);
+    assert_eq!(ticks(&mut runner, 6), ["\\0every 2", "\\0every 3", "\\0every 4"]);
+}
+
+#[test]
+fn waiting_handlers_resume_after_their_time() {
+    let mut runner = ghost(r#"shiori.event.OnWait = function()
+    shiori.wait(2)
+    S "waited"
+end"#).load();
+    get(&mut runner, "OnWait", &[]);
+    assert!(ticks(&mut runner, 1).is_empty());
+    assert_eq!(ticks(&mut runner, 1), ["\\0waited"]);
+    assert!(ticks(&mut runner, 3).is_empty());
+}
+
+#[test]
+fn due_timers_wait_for_a_tick_nothing_responded_to() {
+    let mut runner = ghost(r#"shiori.after(1, say("after"))
+shiori.event.OnBusy = function()
+    for _ = 1, 2 do
+        shiori.resume_on_event("OnSecondChange")
+        S "busy"
+    end
+end"#).load();
+    get(&mut runner, "OnBusy", &[]);
+    assert_eq!(ticks(&mut runner, 2), ["\\0busy", "\\0busy"]);
+    assert_eq!(ticks(&mut runner, 1), ["\\0after 1"]);
+}
+
+#[test]
+fn only_one_timer_runs_per_tick() {
+    let mut runner = ghost(r#"shiori.after(1, say("first"))
+shiori.after(1, say("second"))"#).load();
+    assert_eq!(ticks(&mut runner, 3), ["\\0first 1", "\\0second 1"]);
+}
+
+#[test]
+fn cancelled_timers_do_not_run() {
+    let mut runner = ghost(r#"local once = shiori.after(2, say("after"))
+local repeating = shiori.every(1, say("every"))
+shiori.event.OnCancel = function() once:cancel() repeating:cancel() end"#).load();
+    assert_eq!(ticks(&mut runner, 1), ["\\0every 1"]);
+    get(&mut runner, "OnCancel", &[]);
+    assert!(ticks(&mut runner, 5).is_empty());
+}
+
+#[test]
+fn reloading_replaces_callbacks_and_keeps_waiting_handlers() {
+    let ghost = ghost(r#"shiori.every(2, say("old"))
+shiori.event.OnWait = function()
+    shiori.wait(3)
+    S "waited"
+end"#);
+    let mut runner = ghost.load();
+    get(&mut runner, "OnWait", &[]);
+    assert_eq!(ticks(&mut runner, 2), ["\\0old 1"]);
+
+    ghost.write("init.lua", r#"shiori.every(2, function() shiori.CharacterSet(0) "new" end)"#);
+    runner.fire(&Event::notify("OnShioriReload", &[]));
+    assert_eq!(ticks(&mut runner, 1), ["\\0waited"]);
+    assert_eq!(ticks(&mut runner, 4), ["\\0new", "\\0new"]);
+}
+
+#[test]
+fn failed_reloads_keep_the_old_callbacks() {
+    let ghost = ghost(r#"shiori.every(2, say("old"))"#);
+    let mut runner = ghost.load();
+    ghost.write("init.lua", "shiori.every(1, function() end)\nnot lua");
+    runner.fire(&Event::notify("OnShioriReload", &[]));
+    assert_eq!(ticks(&mut runner, 4), ["\\0old 1", "\\0old 2"]);
+}
+
+#[test]
+fn callbacks_that_raise_errors_are_reported_and_keep_their_schedule() {
+    let mut runner = ghost(r#"shiori.every(2, function() S(nobody.name) end)
+shiori.after(3, say("after"))"#).load();
+    assert!(ticks(&mut runner, 1).is_empty());
+    let balloon = ticks(&mut runner, 1);
+    assert_eq!(balloon.len(), 1);
+    assert!(balloon[0].contains("attempt to index a nil value (global 'nobody')"), "{:?}", balloon);
+    // The failed timer still repeats, and the others still run.
+    assert_eq!(ticks(&mut runner, 1), ["\\0after 1"]);
+    assert!(ticks(&mut runner, 1)[0].contains("attempt to index a nil value"));
+    let mut runner = ghost(r#"shiori.after(1, function() S(nobody.name) end)"#).load();
+    assert_eq!(ticks(&mut runner, 1).len(), 1);
+    assert!(ticks(&mut runner, 5).is_empty());
+}
+
+#[test]
+fn ticks_faster_than_the_system_clock_do_not_run_timers_early() {
+    // Without the runner's clock, timers go by the system clock, as they do in the baseware.
+    let ghost = ghost(r#"shiori.at(os.time() + 2, say("at"))
+shiori.after(2, say("after"))"#);
+    let mut runner = Runner::<LuaShiori>::load(ghost.path.clone()).unwrap();
+    assert!(ticks(&mut runner, 5).is_empty());
+}
@@ -2,7 +2,6 @@ extern crate proc_macro;
 use std::env;
 use quote::quote;
 use syn::export::TokenStream2 as TokenStream;
-use syn::export::Span;
 
 #[proc_macro_derive(RequestType, attributes(shiori))]
 pub fn derive_request_type(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
@@ -10,27 +9,24 @@ pub fn derive_request_type(input: proc_macro::TokenStream) -> proc_macro::TokenS
     request_type::derive(ast).into()
 }
 
-fn wrap_in_const(trait_: &str, ty: &syn::Ident, code: TokenStream) -> TokenStream {
-    let dummy_const = syn::Ident::new(
-        &format!("_IMPL_{}_FOR_{}", trait_, ty.to_string().trim_start_matches("r#").to_owned()),
-        Span::call_site(),
-    );
-
+/// Wraps `code` in an anonymous `const`, so that it can refer to rust-shiori as `_rust_shiori` wherever the derive is
+/// used. The `const` must be anonymous, since impls inside a named one trigger the `non_local_definitions` lint.
+fn wrap_in_const(code: TokenStream) -> TokenStream {
     if env::var("CARGO_PKG_NAME").unwrap() == "rust-shiori" {
-        return quote! {
+        quote! {
             #[allow(non_upper_case_globals, unused_attributes, unused_qualifications)]
-            const #dummy_const: () = {
+            const _: () = {
                 use crate as _rust_shiori;
                 #code
             };
         }
     }
     else {
-        return quote! {
+        quote! {
             #[allow(non_upper_case_globals, unused_attributes, unused_qualifications)]
-            const #dummy_const: () = {
+            const _: () = {
                 #[allow(unknown_lints)]
-                #[cfg_attr(feature = "cargo-clippy", allow(useless_attribute))]
+                #[allow(clippy::useless_attribute)]
                 #[allow(rust_2018_idioms)]
                 extern crate rust_shiori as _rust_shiori;
                 #code
@@ -43,10 +39,10 @@ mod request_type {
     use syn::{Token, export::{TokenStream2 as TokenStream, Span}};
     use quote::quote;
 
-    pub fn parse_shiori_attr<'a>(attr: &'a syn::Attribute, key: &str) -> Option<syn::Lit> {
+    pub fn parse_shiori_attr(attr: &syn::Attribute, key: &str) -> Option<syn::Lit> {
         if attr.path.segments.len() == 1 && attr.path.segments[0].ident == "shiori" {
             if let Ok(syn::Meta::List(mlist)) = attr.parse_meta() {
-                if let Some(syn::NestedMeta::Meta(syn::Meta::NameValue(nv))) = mlist.nested.iter().nth(0) {
+                if let Some(syn::NestedMeta::Meta(syn::Meta::NameValue(nv))) = mlist.nested.iter().next() {
                     if nv.ident == key {
                         return Some(nv.lit.clone())
                     }
@@ -63,7 +59,7 @@ mod request_type {
                 return Some((s.clone(), n))
             }
             None
-        }).nth(0);
+        }).next();
         if let Some((_, i)) = id {
             ast.attrs.remove(i);
         }
@@ -93,10 +89,11 @@ mod request_type {
             syn::Data::Enum(_) => panic!("#[derive(RequestType)] does not support enums!"),
         };
 
+        let mut field_values = Vec::new();
         let initializer = match fields {
             Some(f) => {
                 let mut punc = syn::punctuated::Punctuated::<_, Token![,]>::new();
-                punc.extend(f.iter_mut().map(|f| {
+                punc.extend(f.iter_mut().enumerate().map(|(n, f)| {
                     let field_ident = &f.ident;
                     let shiori_field = f.attrs.iter().enumerate().filter_map(
                         |(n, a)| {
@@ -107,7 +104,7 @@ mod request_type {
                             }
                             None
                         }
-                    ).nth(0);
+                    ).next();
                     if let Some((_, i)) = shiori_field {
                         f.attrs.remove(i);
                     }
@@ -115,6 +112,13 @@ mod request_type {
                     let value = quote! { 
                         <_ as _rust_shiori::request::FromRequestField>::from_request_field(untyped.get_field(#shiori_field))? 
                     };
+                    let (key, member) = match field_ident {
+                        Some(ident) => (syn::LitStr::new(&ident.to_string(), ident.span()), quote! { #ident }),
+                        None => (shiori_field.clone().unwrap_or_else(|| syn::LitStr::new(&n.to_string(), Span::call_site())), { let n = syn::Index::from(n); quote! { #n } }),
+                    };
+                    field_values.push(quote! {
+                        (#key, _rust_shiori::request::ToRequestField::to_request_field(&self.#member)?)
+                    });
                     match field_ident {
                         Some(ident) => { quote! { #ident: #value } },
                         None => value
@@ -143,7 +147,7 @@ mod request_type {
         new_generics.params.push(syn::GenericParam::Lifetime(lifetime_def.clone()));
         let (impl_generics, ..) = new_generics.split_for_impl();
 
-        crate::wrap_in_const("REQUESTTYPE", name, quote! {
+        crate::wrap_in_const(quote! {
             #[automatically_derived]
             impl #impl_generics _rust_shiori::request::typed::RequestType<#lifetime> for #name #ty_generics #where_clause {
                 const ID: &'static str = #id;
@@ -151,6 +155,9 @@ mod request_type {
                     if untyped.get_field("ID") != Some(Self::ID) { return Err(()) }
                     Ok(#initializer)
                 }
+                fn field_values(&self) -> Result<Vec<(&'static str, _rust_shiori::request::FieldValue<'_>)>, ()> {
+                    Ok(vec![#(#field_values),*])
+                }
             }
         }) 
     }
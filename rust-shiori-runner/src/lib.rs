@@ -0,0 +1,331 @@
+//! A minimal stand-in for the baseware, which drives any `Shiori` in-process through the standard lifecycle
+//! and a list of scripted events. Intended for trying out dialogue without starting SSP.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rust_shiori::{
+    Shiori,
+    internals::{self, handle_request},
+    record::{self, Difference, Exchange},
+    request::{Request, Method},
+    response::Response,
+};
+
+pub const SENDER: &str = "shiori-runner";
+
+/// A single event to send to the SHIORI.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub method: Method,
+    pub id: String,
+    pub references: Vec<String>,
+}
+
+impl Event {
+    pub fn get(id: &str, references: &[&str]) -> Self {
+        Event { method: Method::Get, id: id.to_string(), references: references.iter().map(|r| r.to_string()).collect() }
+    }
+
+    pub fn notify(id: &str, references: &[&str]) -> Self {
+        Event { method: Method::Notify, ..Event::get(id, references) }
+    }
+
+    pub fn to_request(&self) -> Request {
+        let mut request = Request::new(self.method)
+            .with_field("Charset", "UTF-8")
+            .with_field("Sender", SENDER)
+            .with_field("SecurityLevel", "local");
+        if self.method != Method::Teach {
+            request = request.with_field("ID", &self.id); // TEACH requests have no ID.
+        }
+        for (i, reference) in self.references.iter().enumerate() {
+            request = request.with_field(&format!("Reference{}", i), reference);
+        }
+        request
+    }
+}
+
+/// One line of an event script. See `Step::parse` for the format.
+#[derive(Clone, Debug)]
+pub enum Step {
+    Event(Event),
+    Wait(u64),
+}
+
+impl Step {
+    /// Parses a line of an event script. Each line is one of:
+    /// - `[GET|NOTIFY] <ID>[<TAB><Reference0>[<TAB><Reference1>...]]`, which fires an event (`GET` if the method is
+    ///   omitted).
+    /// - `TEACH<TAB><word>[<TAB><previous word>...]`, which sends a TEACH request, as if the user typed into the
+    ///   teach box.
+    /// - `wait <seconds>`, which fires that many `OnSecondChange` ticks.
+    ///
+    /// Blank lines and lines beginning with `#` are ignored.
+    pub fn parse(line: &str) -> Result<Option<Step>, String> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            return Ok(None)
+        }
+
+        if let Some(seconds) = line.strip_prefix("wait ") {
+            return seconds.trim().parse().map(|s| Some(Step::Wait(s))).map_err(|_| format!("Invalid wait time: {}", line))
+        }
+
+        let mut parts = line.split('\t');
+        let head = parts.next().unwrap().trim();
+        let (method, id) = match head.split_once(' ') {
+            None if head == "TEACH" => (Method::Teach, "OnTeach"),
+            Some(("GET", id)) => (Method::Get, id.trim()),
+            Some(("NOTIFY", id)) => (Method::Notify, id.trim()),
+            Some(_) => return Err(format!("Invalid event line: {}", line)),
+            None => (Method::Get, head),
+        };
+        Ok(Some(Step::Event(Event { method, id: id.to_string(), references: parts.map(|r| r.to_string()).collect() })))
+    }
+}
+
+/// The time as the runner tells it: the time it started at, plus a second for every tick since. Shared by every
+/// clone, so it can be given to a SHIORI that keeps time, such as `LuaShiori::set_clock`, to keep it in step with the
+/// ticks, which come much faster than once a second.
+#[derive(Clone, Debug)]
+pub struct Clock {
+    start: i64,
+    seconds: Arc<AtomicU64>,
+}
+
+impl Clock {
+    fn new() -> Self {
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+        Clock { start, seconds: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// The current time, in seconds since the Unix epoch.
+    pub fn now(&self) -> i64 {
+        self.start + self.seconds() as i64
+    }
+
+    fn seconds(&self) -> u64 {
+        self.seconds.load(Ordering::Relaxed)
+    }
+
+    fn tick(&self) -> u64 {
+        self.seconds.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+pub struct Runner<S: Shiori> {
+    shiori: S,
+    clock: Clock,
+    idle_seconds: u64,
+}
+
+impl<S: Shiori> Runner<S> {
+    pub fn load(path: PathBuf) -> Result<Self, S::LoadError> {
+        S::load(path).map(Runner::new)
+    }
+
+    pub fn new(shiori: S) -> Self {
+        Runner { shiori, clock: Clock::new(), idle_seconds: 0 }
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    pub fn shiori(&mut self) -> &mut S {
+        &mut self.shiori
+    }
+
+    /// Records all further traffic, if the SHIORI asks for that. `ghost_path` is the directory it was loaded from.
+    pub fn start_recording(&self, ghost_path: &Path) {
+        internals::start_recording(ghost_path, &self.shiori);
+    }
+
+    /// Sends `event` to the SHIORI over the same text protocol the baseware uses, so that it is recorded if the
+    /// SHIORI asks for that.
+    pub fn fire(&mut self, event: &Event) -> Response {
+        let response = handle_request(&event.to_request().to_wire(), &mut self.shiori);
+        Response::parse(&response).expect("The SHIORI returned a malformed response.")
+    }
+
+    /// Advances the clock by one second, firing `OnSecondChange` (and `OnMinuteChange` on the minute).
+    pub fn tick(&mut self, on_response: &mut impl FnMut(&Event, &Response)) {
+        let seconds = self.clock.tick();
+        self.idle_seconds += 1;
+        let hours = (seconds / 3600).to_string();
+        let idle = self.idle_seconds.to_string();
+        let references = [hours.as_str(), "0", "0", "1", idle.as_str()];
+
+        self.report(&Event::get("OnSecondChange", &references), on_response);
+        if seconds.is_multiple_of(60) {
+            self.report(&Event::get("OnMinuteChange", &references), on_response);
+        }
+    }
+
+    /// Boots the SHIORI, runs `steps` with `ticks` seconds between each, then closes it, reporting every
+    /// response to `on_response`.
+    pub fn run(&mut self, steps: &[Step], ticks: u64, mut on_response: impl FnMut(&Event, &Response)) {
+        self.report(&Event::get("OnInitialize", &[]), &mut on_response);
+        self.report(&Event::get("OnBoot", &["master"]), &mut on_response);
+        for step in steps {
+            for _ in 0..ticks { self.tick(&mut on_response) }
+            match step {
+                Step::Event(e) => { self.idle_seconds = 0; self.report(e, &mut on_response) },
+                Step::Wait(s) => for _ in 0..*s { self.tick(&mut on_response) },
+            }
+        }
+        for _ in 0..ticks { self.tick(&mut on_response) }
+        self.report(&Event::get("OnClose", &["user"]), &mut on_response);
+        self.report(&Event::get("OnDestroy", &[]), &mut on_response);
+        self.shiori.unload();
+    }
+
+    /// Replays a recording read with `record::read_session` instead of running events, then closes the SHIORI.
+    /// Returns every response that differs from the recording.
+    pub fn replay(&mut self, exchanges: &[Exchange]) -> Vec<Difference> {
+        let differences = record::replay(&mut self.shiori, exchanges);
+        self.shiori.unload();
+        differences
+    }
+
+    fn report(&mut self, event: &Event, on_response: &mut impl FnMut(&Event, &Response)) {
+        let response = self.fire(event);
+        on_response(event, &response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_shiori::response::{ResponseBuilder, ResponseStatus};
+
+    /// Answers every GET with its ID and references, and remembers every request it was sent.
+    #[derive(Default)]
+    struct Echo {
+        requests: Vec<Request>,
+        unloaded: bool,
+    }
+
+    impl Shiori for Echo {
+        type LoadError = ();
+
+        fn load(_: PathBuf) -> Result<Self, ()> {
+            Ok(Echo::default())
+        }
+
+        fn respond(&mut self, request: Request) -> Response {
+            let mut value = request.get_field("ID").unwrap_or("").to_string();
+            for i in 0.. {
+                match request.get_field(&format!("Reference{}", i)) {
+                    Some(reference) => { value.push(' '); value.push_str(reference) },
+                    None => break,
+                }
+            }
+            let response = match request.method() {
+                Method::Get => ResponseBuilder::new().with_status(ResponseStatus::OK).with_field("Value", &value),
+                _ => ResponseBuilder::new().with_status(ResponseStatus::NoContent),
+            };
+            self.requests.push(request);
+            response.build().unwrap()
+        }
+
+        fn unload(&mut self) {
+            self.unloaded = true;
+        }
+    }
+
+    /// The method, ID and references of the event on `line`.
+    fn event(line: &str) -> (Method, String, Vec<String>) {
+        match Step::parse(line) {
+            Ok(Some(Step::Event(e))) => (e.method, e.id, e.references),
+            other => panic!("{:?} parsed as {:?}", line, other),
+        }
+    }
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn ids(runner: &Runner<Echo>) -> Vec<&str> {
+        runner.shiori.requests.iter().map(|r| r.get_field("ID").unwrap_or("(TEACH)")).collect()
+    }
+
+    #[test]
+    fn parses_events() {
+        assert_eq!(event("OnBoot\tmaster\t\tshell"), (Method::Get, "OnBoot".into(), strings(&["master", "", "shell"])));
+        assert_eq!(event("GET  OnMouseClick \t0\r\n"), (Method::Get, "OnMouseClick".into(), strings(&["0"])));
+        assert_eq!(event("NOTIFY OnNotifyOSInfo"), (Method::Notify, "OnNotifyOSInfo".into(), vec![]));
+        let teach = (Method::Teach, "OnTeach".into(), strings(&["word", "earlier word"]));
+        assert_eq!(event("TEACH\tword\tearlier word"), teach);
+        let teach = Event { method: Method::Teach, id: "OnTeach".into(), references: vec![] };
+        assert!(!teach.to_request().fields().contains_key("ID"));
+    }
+
+    #[test]
+    fn parses_waits_and_skips_comments() {
+        assert!(matches!(Step::parse("wait 30"), Ok(Some(Step::Wait(30)))));
+        assert!(matches!(Step::parse("wait  5 "), Ok(Some(Step::Wait(5)))));
+        for line in ["", "   ", "# OnBoot", "  # wait 5", "\r\n"] {
+            assert!(matches!(Step::parse(line), Ok(None)), "{:?}", line);
+        }
+        for line in ["wait soon", "wait -1", "POST OnBoot", "TEACH word"] {
+            assert!(Step::parse(line).is_err(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn ticks_count_hours_idle_time_and_minutes() {
+        let mut runner = Runner::new(Echo::default());
+        let start = runner.clock().now();
+        let mut responses = Vec::new();
+        for _ in 0..60 {
+            runner.tick(&mut |_, response| responses.push(response.get_field::<String>("Value").unwrap().unwrap()));
+        }
+        assert_eq!(runner.clock().now(), start + 60);
+        assert_eq!(responses[0], "OnSecondChange 0 0 0 1 1");
+        assert_eq!(&responses[59..], ["OnSecondChange 0 0 0 1 60", "OnMinuteChange 0 0 0 1 60"]);
+        assert_eq!(responses.len(), 61);
+    }
+
+    #[test]
+    fn runs_events_between_ticks_through_the_lifecycle() {
+        let mut runner = Runner::new(Echo::default());
+        let steps: Vec<_> = ["OnMouseClick", "wait 2", "NOTIFY OnOther"].iter()
+            .map(|line| Step::parse(line).unwrap().unwrap())
+            .collect();
+        let mut values = Vec::new();
+        runner.run(&steps, 1, |_, response| values.extend(response.get_field::<String>("Value").map(Result::unwrap)));
+        assert_eq!(ids(&runner), [
+            "OnInitialize", "OnBoot", "OnSecondChange", "OnMouseClick", "OnSecondChange", "OnSecondChange",
+            "OnSecondChange", "OnSecondChange", "OnOther", "OnSecondChange", "OnClose", "OnDestroy",
+        ]);
+        // Firing an event resets the idle time, the fifth reference. The NOTIFY has no value.
+        assert_eq!(values[4..9], ["OnSecondChange 0 0 0 1 1", "OnSecondChange 0 0 0 1 2", "OnSecondChange 0 0 0 1 3",
+            "OnSecondChange 0 0 0 1 4", "OnSecondChange 0 0 0 1 1"]);
+        assert!(runner.shiori.unloaded);
+    }
+
+    #[test]
+    fn replays_recordings_and_reports_differences() {
+        let recording = [("OnBoot", "OnBoot master"), ("OnClose", "Goodbye")]
+            .iter()
+            .map(|(id, value)| {
+                let event = Event::get(id, &if *id == "OnBoot" { vec!["master"] } else { vec![] });
+                let response = ResponseBuilder::new().with_status(ResponseStatus::OK).with_field("Value", value);
+                format!(">>> 1\r\n{}<<< 2\r\n{}", event.to_request().to_wire(), response.build().unwrap().to_wire())
+            })
+            .collect::<String>();
+        let session = record::read_session(&recording).unwrap();
+
+        let mut runner = Runner::new(Echo::default());
+        let differences = runner.replay(&session);
+        assert_eq!(ids(&runner), ["OnBoot", "OnClose"]);
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].index, 1);
+        assert!(differences[0].actual.contains("Value: OnClose"));
+        assert!(runner.shiori.unloaded);
+    }
+}
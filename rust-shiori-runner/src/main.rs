@@ -0,0 +1,120 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+
+use rust_shiori::{record, response::ResponseStatus};
+use rust_shiori_lua::LuaShiori;
+use rust_shiori_runner::{Runner, Step};
+
+const USAGE: &str = "\
+Usage: shiori-runner [OPTIONS] <GHOST_DIR>
+
+Loads the Lua SHIORI from GHOST_DIR (the directory containing rust-shiori.toml), boots it, fires the events in
+the event script, closes it, and prints every SakuraScript it returns. Each OnSecondChange tick counts as a second
+for the ghost's timers, however fast the ticks are fired.
+
+Options:
+    -e, --events <FILE>   Event script to run, or - for stdin. One event per line: `[GET|NOTIFY] <ID>` followed by
+                          tab-separated references, `TEACH` followed by the tab-separated words taught so far,
+                          or `wait <seconds>`. Lines starting with # are ignored.
+    -t, --ticks <N>       OnSecondChange ticks to fire between events (default 1).
+    -r, --replay <FILE>   Instead of running events, replay a recording made with `[logging] record` and print
+                          every response that differs from it.
+    -v, --verbose         Also print events that returned no script.
+    -h, --help            Print this message.";
+
+struct Args {
+    ghost: PathBuf,
+    events: Option<String>,
+    replay: Option<String>,
+    ticks: u64,
+    verbose: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = std::env::args().skip(1);
+    let (mut ghost, mut events, mut replay, mut ticks, mut verbose) = (None, None, None, 1, false);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--events" => events = Some(args.next().ok_or("Missing value for --events.")?),
+            "-r" | "--replay" => replay = Some(args.next().ok_or("Missing value for --replay.")?),
+            "-t" | "--ticks" => ticks = args.next().and_then(|t| t.parse().ok()).ok_or("Invalid value for --ticks.")?,
+            "-v" | "--verbose" => verbose = true,
+            "-h" | "--help" => { println!("{}", USAGE); process::exit(0) },
+            _ if ghost.is_none() => ghost = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    Ok(Args { ghost: ghost.ok_or("Missing ghost directory.")?, events, replay, ticks, verbose })
+}
+
+fn read_steps(events: &Option<String>) -> Result<Vec<Step>, String> {
+    let text = match events.as_ref().map(String::as_str) {
+        None => return Ok(Vec::new()),
+        Some("-") => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).map_err(|e| e.to_string())?;
+            text
+        },
+        Some(path) => fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?,
+    };
+    let mut steps = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        if let Some(step) = Step::parse(line).map_err(|e| format!("Line {}: {}", n + 1, e))? {
+            steps.push(step);
+        }
+    }
+    Ok(steps)
+}
+
+fn replay(runner: &mut Runner<LuaShiori>, path: &str) -> i32 {
+    let session = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path, e))
+        .and_then(|text| record::read_session(&text));
+    let session = match session {
+        Ok(s) => s,
+        Err(e) => { eprintln!("{}", e); return 2 },
+    };
+
+    let differences = runner.replay(&session);
+    for difference in &differences {
+        println!("{}\n", difference);
+    }
+    println!("{} of {} responses differ.", differences.len(), session.len());
+    if differences.is_empty() { 0 } else { 1 }
+}
+
+fn main() {
+    let (args, steps) = match parse_args().and_then(|a| read_steps(&a.events).map(|s| (a, s))) {
+        Ok(r) => r,
+        Err(e) => { eprintln!("{}\n\n{}", e, USAGE); process::exit(2) },
+    };
+
+    let mut runner = match Runner::<LuaShiori>::load(args.ghost.clone()) {
+        Ok(r) => r,
+        Err(e) => { eprintln!("{}", e); process::exit(1) },
+    };
+
+    if let Some(path) = &args.replay {
+        process::exit(replay(&mut runner, path));
+    }
+    let clock = runner.clock();
+    runner.shiori().set_clock(move || clock.now());
+    runner.start_recording(&args.ghost);
+
+    runner.run(&steps, args.ticks, |event, response| {
+        let value: Option<String> = response.get_field("Value").and_then(Result::ok).filter(|v: &String| !v.is_empty());
+        let verbose = args.verbose;
+        if value.is_none() && !response.status().is_error() && !verbose {
+            return
+        }
+        println!("[{} {}] {}", event.method.as_str(), event.id, response.status().as_str());
+        if let Some(value) = value {
+            println!("{}", value);
+        }
+        if response.status() == ResponseStatus::InternalServerError {
+            println!("(see the SHIORI log for details)");
+        }
+    });
+}
@@ -0,0 +1,57 @@
+//! Identifies the request being handled, so that log records made while handling it can be told apart from those of
+//! other requests. `internals::handle_request` enters a context for every request, and loggers can include
+//! `RequestContext::current()` in their records.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CURRENT: RefCell<Option<RequestContext>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestContext {
+    /// Sequential number of the request, starting from 1 for the first request in the process.
+    pub id: u64,
+    /// The request's `ID` field, i.e. the event, if it has one.
+    pub event: Option<String>,
+}
+
+impl RequestContext {
+    /// The context of the request being handled on this thread, if any.
+    pub fn current() -> Option<RequestContext> {
+        CURRENT.with(|c| c.borrow().clone())
+    }
+
+    /// Assigns the next id to a request for `event`, which is the current request on this thread until the returned
+    /// guard is dropped.
+    pub fn enter(event: Option<&str>) -> ContextGuard {
+        let context = RequestContext { id: NEXT_ID.fetch_add(1, Ordering::Relaxed), event: event.map(String::from) };
+        ContextGuard { previous: CURRENT.with(|c| c.replace(Some(context))) }
+    }
+}
+
+/// Formats as `#12 OnBoot`, or just `#12` if the request has no `ID`.
+impl fmt::Display for RequestContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.id)?;
+        match &self.event {
+            Some(event) => write!(f, " {}", event),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Makes the context that was current before `RequestContext::enter` current again when dropped.
+pub struct ContextGuard {
+    previous: Option<RequestContext>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|c| *c.borrow_mut() = self.previous.take());
+    }
+}
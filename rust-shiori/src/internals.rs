@@ -1,21 +1,55 @@
+#[cfg(windows)]
 use winapi::shared::minwindef::{TRUE, FALSE};
 
+use std::path::Path;
+use std::sync::Mutex;
+
+#[cfg(windows)]
 use shiori_hglobal::GStr;
+use lazy_static::lazy_static;
 use log::{debug, warn, error};
 
 use crate::{Request, Shiori, SHIORI_VERSION};
+use crate::context::RequestContext;
+use crate::record::Recorder;
 
+#[cfg(windows)]
 pub use winapi::ctypes::c_long;
+#[cfg(windows)]
 pub use winapi::shared::minwindef::{BOOL, HGLOBAL};
 
+lazy_static! {
+    static ref RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+}
+
+#[cfg(windows)]
 pub unsafe fn load<S: Shiori>(path: HGLOBAL, len: c_long, shiori: &mut Option<S>) -> BOOL {
     let path_str = GStr::capture(path, len as usize); // TODO: PR to shiori_hglobal: use c_long
-    match path_str.to_ansi_str().map(|s| S::load(s.into())) {
-        Ok(Ok(s)) => { *shiori = Some(s); TRUE },
+    match path_str.to_ansi_str().map(|s| (S::load(s.clone().into()), s)) {
+        Ok((Ok(s), path)) => { start_recording(path.as_ref(), &s); *shiori = Some(s); TRUE },
         _ => { error!("The SHIORI failed to load."); FALSE },
     }
 }
 
+/// Starts recording traffic to the file `shiori` asks for, if any. See `record` for the format.
+pub fn start_recording(ghost_path: &Path, shiori: &impl Shiori) {
+    if let Some(record_path) = shiori.record_path() {
+        match Recorder::open(&ghost_path.join(&record_path)) {
+            Ok(r) => *RECORDER.lock().unwrap() = Some(r),
+            Err(e) => error!("Could not open {} to record SHIORI traffic. Details: {}", record_path.display(), e),
+        }
+    }
+}
+
+fn record(write: impl FnOnce(&mut Recorder) -> std::io::Result<()>) {
+    let mut recorder = RECORDER.lock().unwrap();
+    if let Some(Err(e)) = recorder.as_mut().map(write) {
+        error!("Failed to record SHIORI traffic, so recording has stopped. Details: {}", e);
+        *recorder = None;
+    }
+}
+
+#[cfg(windows)]
 pub fn unload(shiori: &mut Option<impl Shiori>) -> BOOL {
     match shiori {
         Some(s) => { s.unload(); TRUE },
@@ -23,14 +57,15 @@ pub fn unload(shiori: &mut Option<impl Shiori>) -> BOOL {
     }
 }
 
+#[cfg(windows)]
 pub unsafe fn request(request: HGLOBAL, len: *mut c_long, shiori: &mut Option<impl Shiori>) -> HGLOBAL {
     match shiori {
         Some(shiori) => {
-            let response = match GStr::capture(request, (*len) as usize).to_utf8_str().map(|s| handle_request(s, shiori)) {
-                Ok(Ok(r)) => r,
-                e @ _ => {
-                    warn!("Recieved a corrupt or incorrectly formatted SHIORI request. Details: {:?}", e);
-                    format!("SHIORI/{} 400 Bad Request\r\n\r\n", SHIORI_VERSION)
+            let response = match GStr::capture(request, (*len) as usize).to_utf8_str() {
+                Ok(s) => handle_request(s, shiori),
+                Err(e) => {
+                    warn!("Recieved a corrupt SHIORI request. Details: {:?}", e);
+                    bad_request()
                 }
             };
             let response_gstr = GStr::clone_from_slice_nofree(response.as_bytes());
@@ -44,17 +79,23 @@ pub unsafe fn request(request: HGLOBAL, len: *mut c_long, shiori: &mut Option<im
     }   
 }
 
-fn handle_request(request: &str, shiori: &mut impl Shiori) -> Result<String, ()> {
+pub fn handle_request(request: &str, shiori: &mut impl Shiori) -> String {
+    let parsed = Request::parse(request);
+    let _context = RequestContext::enter(parsed.as_ref().ok().and_then(|r| r.get_field("ID")));
     debug!("SHIORI REQUEST:\n{}", request);
-    let response = shiori.respond(Request::parse(request)?);
-    let mut response_parts = Vec::new();
-    response_parts.push(format!("SHIORI/{} {}", SHIORI_VERSION, response.status().as_str()));
-    for field in response.fields_iter() {
-        let value: String = response.get_field(field).unwrap().unwrap();
-        response_parts.push(format!("{}: {}", field, value));
-    }
-    // Apparently these must always end with two CRLFs or the encoding detection fails! Fun!
-    let response_str = response_parts.join("\r\n") + "\r\n\r\n"; 
+    record(|r| r.record_request(request));
+    let response_str = match parsed {
+        Ok(r) => shiori.respond(r).to_wire(),
+        Err(_) => {
+            warn!("Recieved an incorrectly formatted SHIORI request.");
+            bad_request()
+        }
+    };
     debug!("SHIORI RESPONSE:\n{}", response_str);
-    Ok(response_str)
+    record(|r| r.record_response(&response_str));
+    response_str
+}
+
+fn bad_request() -> String {
+    format!("SHIORI/{} 400 Bad Request\r\n\r\n", SHIORI_VERSION)
 }
\ No newline at end of file
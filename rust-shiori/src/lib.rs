@@ -2,6 +2,9 @@ use std::path::PathBuf;
 
 pub mod request;
 pub mod response;
+pub mod sstp;
+pub mod record;
+pub mod context;
 
 #[doc(hidden)]
 pub mod internals;
@@ -13,21 +16,27 @@ pub const SHIORI_VERSION: &str = "3.0";
 
 /// This macro turns a rust crate into a SHIORI DLL. The crate must be a `dylib` or a `cdylib` for it work.
 /// Its only argument is a type implementing the `Shiori` trait, which will serve as the SHIORI's implementation.
+/// The exported functions only exist on Windows, so on other platforms the crate can still be built and its
+/// `Shiori` used in-process (e.g. by `shiori-runner`).
 #[macro_export]
 macro_rules! shiori {
     {$shiori:ty} => {
+        #[cfg(windows)]
         static mut SHIORI: Option<$shiori> = None;
 
+        #[cfg(windows)]
         #[no_mangle]
         pub unsafe extern "C" fn load(path: $crate::internals::HGLOBAL, len: $crate::internals::c_long) -> $crate::internals::BOOL {
             $crate::internals::load(path, len, &mut SHIORI)
         }
 
+        #[cfg(windows)]
         #[no_mangle]
         pub unsafe extern "C" fn unload() -> $crate::internals::BOOL {
             $crate::internals::unload(&mut SHIORI)
         }
 
+        #[cfg(windows)]
         #[no_mangle]
         pub unsafe extern "C" fn request(request: $crate::internals::HGLOBAL, len: *mut $crate::internals::c_long) -> $crate::internals::HGLOBAL {
             $crate::internals::request(request, len, &mut SHIORI)
@@ -40,4 +49,8 @@ pub trait Shiori {
     fn load(path: PathBuf) -> Result<Self, Self::LoadError> where Self: Sized;
     fn respond(&mut self, request: Request) -> Response;
     fn unload(&mut self) { }
+
+    /// If this returns a path (relative to the ghost directory), every request and response is appended to that
+    /// file in the format described in `record`.
+    fn record_path(&self) -> Option<PathBuf> { None }
 }
\ No newline at end of file
@@ -0,0 +1,150 @@
+//! Recording and replaying of raw SHIORI traffic, so that a session from a bug report can be reproduced, or
+//! turned into a regression test.
+//!
+//! # Format
+//! A recording is a UTF-8 text file consisting of any number of entries, each of which is a marker line followed
+//! by a complete SHIORI message exactly as it was sent or returned:
+//!
+//! ```text
+//! >>> <timestamp>
+//! GET SHIORI/3.0
+//! ID: OnBoot
+//! ...
+//!
+//! <<< <timestamp>
+//! SHIORI/3.0 200 OK
+//! Value: \0Hello!\e
+//! ...
+//!
+//! ```
+//!
+//! `>>>` marks a request and `<<<` marks the response to the request before it. The timestamp is the number of
+//! milliseconds since the Unix epoch at which the message was received or sent. Every message ends with an empty
+//! line, as it does on the wire, and lines end with CRLF. Since SHIORI fields cannot contain line breaks, an
+//! empty line always ends a message.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Shiori, Response, internals::handle_request};
+
+const REQUEST_MARKER: &str = ">>> ";
+const RESPONSE_MARKER: &str = "<<< ";
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Appends SHIORI traffic to a recording.
+pub struct Recorder {
+    file: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Recorder { file: BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?) })
+    }
+
+    pub fn record_request(&mut self, request: &str) -> io::Result<()> {
+        self.record(REQUEST_MARKER, request)
+    }
+
+    pub fn record_response(&mut self, response: &str) -> io::Result<()> {
+        self.record(RESPONSE_MARKER, response)
+    }
+
+    fn record(&mut self, marker: &str, message: &str) -> io::Result<()> {
+        write!(self.file, "{}{}\r\n{}", marker, now(), message)?;
+        if !message.ends_with("\r\n\r\n") { self.file.write_all(b"\r\n")?; }
+        self.file.flush()
+    }
+}
+
+/// A request and the response that was recorded for it.
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    pub request_time: u64,
+    pub request: String,
+    pub response_time: u64,
+    pub response: String,
+}
+
+/// Reads every complete exchange from a recording. A trailing request with no response (e.g. because the
+/// baseware crashed while it was being handled) is returned with an empty response. Lines may end in `\n` as well
+/// as `\r\n`, in case the recording was edited by hand, but messages are always returned with `\r\n`.
+pub fn read_session(text: &str) -> Result<Vec<Exchange>, String> {
+    let mut exchanges = Vec::new();
+    let mut pending: Option<(u64, String)> = None;
+    let mut lines = text.lines().enumerate();
+    while let Some((n, line)) = lines.next() {
+        if line.is_empty() { continue }
+
+        let (is_request, time) = if let Some(time) = line.strip_prefix(REQUEST_MARKER) {
+            (true, time)
+        } else if let Some(time) = line.strip_prefix(RESPONSE_MARKER) {
+            (false, time)
+        } else {
+            return Err(format!("Line {}: expected a `>>>` or `<<<` marker, found `{}`.", n + 1, line))
+        };
+        let time = time.parse().map_err(|_| format!("Line {}: invalid timestamp `{}`.", n + 1, time))?;
+
+        let mut message = String::new();
+        for (_, line) in lines.by_ref() {
+            if line.is_empty() { break }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message.push_str("\r\n");
+
+        match (is_request, pending.take()) {
+            (true, None) => pending = Some((time, message)),
+            (false, Some((request_time, request))) => exchanges.push(Exchange {
+                request_time, request, response_time: time, response: message,
+            }),
+            (true, Some(_)) => return Err(format!("Line {}: a request has no recorded response.", n + 1)),
+            (false, None) => return Err(format!("Line {}: a response has no recorded request.", n + 1)),
+        }
+    }
+    if let Some((request_time, request)) = pending {
+        exchanges.push(Exchange { request_time, request, response_time: 0, response: String::new() });
+    }
+    Ok(exchanges)
+}
+
+/// A response that differed from its recording during a replay.
+pub struct Difference {
+    pub index: usize,
+    pub request: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Exchange {} differs. Request:\n{}", self.index, self.request.trim_end())?;
+        writeln!(f, "Expected:\n{}", self.expected.trim_end())?;
+        write!(f, "Actual:\n{}", self.actual.trim_end())
+    }
+}
+
+/// Feeds every recorded request into `shiori` in order, and returns each response that does not match its
+/// recording. Responses are compared by status and fields, so field order does not matter.
+pub fn replay(shiori: &mut impl Shiori, exchanges: &[Exchange]) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    for (index, exchange) in exchanges.iter().enumerate() {
+        let actual = handle_request(&exchange.request, shiori);
+        let matches = match (Response::parse(&exchange.response), Response::parse(&actual)) {
+            (Ok(e), Ok(a)) => e == a,
+            _ => exchange.response == actual,
+        };
+        if !matches {
+            differences.push(Difference {
+                index, request: exchange.request.clone(), expected: exchange.response.clone(), actual,
+            });
+        }
+    }
+    differences
+}
@@ -1,9 +1,13 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::str::FromStr;
 
 use lazy_static::lazy_static;
 use regex::Regex;
 
+use crate::SHIORI_VERSION;
+use crate::sstp::PassThru;
+
 #[cfg(feature = "typed_request")]
 pub mod typed;
 
@@ -17,7 +21,19 @@ lazy_static! {
     ).unwrap();
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+/// Collects every `Name: Value` line of a SHIORI or SSTP message into a map.
+pub(crate) fn parse_fields(text: &str) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    for captures in REQUEST_FIELD.captures_iter(text) {
+        fields.insert(
+            captures.name("field").unwrap().as_str().to_string(), 
+            captures.name("value").unwrap().as_str().to_string(),
+        );
+    }
+    fields
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Method {
     Get,
     Notify,
@@ -46,6 +62,7 @@ impl FromStr for Method {
     }
 }
 
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Request {
     method: Method,
     version: String,
@@ -53,19 +70,24 @@ pub struct Request {
 }
 
 impl Request {
-    pub(crate) fn parse(text: &str) -> Result<Request, ()> {
+    /// Creates a request with no fields, as the baseware would send it. Mostly useful for driving a `Shiori`
+    /// in-process, e.g. from a test or from `shiori-runner`.
+    pub fn new(method: Method) -> Self {
+        Request { method, version: SHIORI_VERSION.to_string(), fields: HashMap::new() }
+    }
+
+    pub fn with_field(mut self, field_name: &str, value: &str) -> Self {
+        self.fields.insert(field_name.to_string(), value.to_string());
+        self
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn parse(text: &str) -> Result<Request, ()> {
         if let Some(header) = REQUEST_HEADER.captures(text) {
-            let mut fields = HashMap::new();
-            for captures in REQUEST_FIELD.captures_iter(text) {
-                fields.insert(
-                    captures.name("field").unwrap().as_str().to_string(), 
-                    captures.name("value").unwrap().as_str().to_string(),
-                );
-            }
             return Ok(Request {
                 method: Method::from_str(header.name("method").unwrap().as_str()).unwrap(),
                 version: header.name("version").unwrap().as_str().to_string(),
-                fields: fields,
+                fields: parse_fields(text),
             })
         }
         Err(())
@@ -84,20 +106,36 @@ impl Request {
     }
 
     pub fn fields_iter(&self) -> impl Iterator<Item=&str> {
-        return self.fields.keys().map(|s| s.as_str())
+        self.fields.keys().map(|s| s.as_str())
     }
 
     pub fn get_field(&self, field: &str) -> Option<&str> {
         self.fields.get(field).map(|s| s.as_str())
     }
 
+    /// The `X-SSTP-PassThru-*` fields of this request, which the baseware copies over from the SSTP request
+    /// that caused it, if any.
+    pub fn passthru(&self) -> PassThru<'_> {
+        PassThru::from_fields(self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+    }
+
+    /// Serializes this request as the baseware would send it.
+    pub fn to_wire(&self) -> String {
+        let mut request_parts = vec![format!("{} SHIORI/{}", self.method.as_str(), self.version)];
+        for (field, value) in &self.fields {
+            request_parts.push(format!("{}: {}", field, value));
+        }
+        request_parts.join("\r\n") + "\r\n\r\n"
+    }
+
     #[cfg(feature = "typed_request")]
-    pub fn as_typed(&self) -> typed::TypedRequest {
+    pub fn as_typed(&self) -> typed::TypedRequest<'_> {
         typed::TypedRequest::from_untyped(self)
     }
 }
 
 pub trait FromRequestField<'a>: Sized {
+    #[allow(clippy::result_unit_err)]
     fn from_request_field(value: Option<&'a str>) -> Result<Self, ()>;
 }
 
@@ -148,4 +186,64 @@ from_request_parse! {
     char
 }
 
+/// The value of a field of a typed request, in a form that can be handed to a scripting language.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldValue<'a> {
+    Missing,
+    Text(&'a str),
+    Integer(i64),
+    Char(char),
+}
+
+/// The inverse of `FromRequestField`, for passing typed requests on to scripts. Fails if the value can't be
+/// represented as a `FieldValue`, like an integer outside the range of `i64`.
+pub trait ToRequestField {
+    #[allow(clippy::result_unit_err)]
+    fn to_request_field(&self) -> Result<FieldValue<'_>, ()>;
+}
+
+impl ToRequestField for &str {
+    fn to_request_field(&self) -> Result<FieldValue<'_>, ()> {
+        Ok(FieldValue::Text(self))
+    }
+}
+
+impl<T> ToRequestField for Option<T> where T: ToRequestField {
+    fn to_request_field(&self) -> Result<FieldValue<'_>, ()> {
+        match self {
+            Some(v) => v.to_request_field(),
+            None => Ok(FieldValue::Missing),
+        }
+    }
+}
+
+impl ToRequestField for char {
+    fn to_request_field(&self) -> Result<FieldValue<'_>, ()> {
+        Ok(FieldValue::Char(*self))
+    }
+}
+
+macro_rules! to_request_integer {
+    { $($name:ty),* } => {
+        $(impl ToRequestField for $name {
+            fn to_request_field(&self) -> Result<FieldValue<'_>, ()> {
+                i64::try_from(*self).map(FieldValue::Integer).map_err(|_| ())
+            }
+        })*
+    }
+}
 
+to_request_integer! {
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize
+}
@@ -1,4 +1,5 @@
-use crate::request::{Method, Request as UntypedReq, FromRequestField};
+use crate::request::{Method, Request as UntypedReq, FromRequestField, ToRequestField, FieldValue};
+use crate::sstp::PassThru;
 pub use rust_shiori_macros::*;
 
 pub struct TypedRequest<'a> {
@@ -7,7 +8,9 @@ pub struct TypedRequest<'a> {
     sender: Option<&'a str>,
     charset: Option<&'a str>,
     security_level: Option<&'a str>,
+    status: Option<&'a str>,
     id: Option<&'a str>,
+    passthru: PassThru<'a>,
     kind: RequestKind<'a>,
 }
 
@@ -19,7 +22,9 @@ impl<'a> TypedRequest<'a> {
             sender: untyped.get_field("Sender"),
             charset: untyped.get_field("Charset"),
             security_level: untyped.get_field("SecurityLevel"),
+            status: untyped.get_field("Status"),
             id: untyped.get_field("ID"),
+            passthru: untyped.passthru(),
             kind: RequestKind::from_untyped(untyped),
         }
     }
@@ -29,41 +34,70 @@ impl<'a> TypedRequest<'a> {
     pub fn sender(&self) -> Option<&str> { self.sender }
     pub fn charset(&self) -> Option<&str> { self.charset }
     pub fn security_level(&self) -> Option<&str> { self.security_level }
+    pub fn status(&self) -> Option<&str> { self.status }
     pub fn id(&self) -> Option<&str> { self.id }
-    pub fn kind(&self) -> &RequestKind { &self.kind }
-}
-
-pub enum RequestKind<'u> {
-    OnFirstBoot(OnFirstBoot),
-    OnBoot(OnBoot<'u>),
-    OnClose(OnClose),
-    OnCloseAll(OnCloseAll),
-    OnGhostChanged(OnGhostChanged<'u>),
-    OnGhostChanging(OnGhostChanging<'u>),
-    OnGhostCalled(OnGhostCalled<'u>),
-    OnGhostCalling(OnGhostCalling<'u>),
-    OnGhostCallComplete(OnGhostCallComplete<'u>),
-    OnOtherGhostBooted(OnOtherGhostBooted<'u>),
-    OnOtherGhostChanged(OnOtherGhostChanged<'u>),
-    OnOtherGhostClosed(OnOtherGhostClosed<'u>),
-    OnShellChanged(OnShellChanged<'u>),
-    OnShellChanging(OnShellChanging<'u>),
-    OnDressupChanged(OnDressupChanged<'u>),
-    Other,
-}
-
-impl<'u> RequestKind<'u> {
-    fn from_untyped(untyped: &'u UntypedReq) -> Self {
-        use self::RequestKind::*;
-        match untyped.get_field("ID").unwrap_or("") {
-            _ => Other
+    pub fn passthru(&self) -> &PassThru<'a> { &self.passthru }
+    pub fn kind(&self) -> &RequestKind<'_> { &self.kind }
+}
+
+macro_rules! request_kinds {
+    { $($name:ident $(<$lt:lifetime>)?),* $(,)? } => {
+        pub enum RequestKind<'u> {
+            $($name($name $(<$lt>)?),)*
+            Other,
+        }
+
+        impl<'u> RequestKind<'u> {
+            fn from_untyped(untyped: &'u UntypedReq) -> Self {
+                match untyped.get_field("ID").unwrap_or("") {
+                    $(<$name>::ID => $name::from_untyped(untyped).map(RequestKind::$name).unwrap_or(RequestKind::Other),)*
+                    _ => RequestKind::Other,
+                }
+            }
+
+            /// The named fields of the request, or none if it is not of a known type, is malformed, or has a field
+            /// that can't be represented as a `FieldValue`.
+            pub fn field_values(&self) -> Vec<(&'static str, FieldValue<'_>)> {
+                match self {
+                    $(RequestKind::$name(r) => r.field_values().unwrap_or_default(),)*
+                    RequestKind::Other => Vec::new(),
+                }
+            }
         }
     }
 }
 
+request_kinds! {
+    OnFirstBoot,
+    OnBoot<'u>,
+    OnClose,
+    OnCloseAll,
+    OnGhostChanged<'u>,
+    OnGhostChanging<'u>,
+    OnGhostCalled<'u>,
+    OnGhostCalling<'u>,
+    OnGhostCallComplete<'u>,
+    OnOtherGhostBooted<'u>,
+    OnOtherGhostChanged<'u>,
+    OnOtherGhostClosed<'u>,
+    OnShellChanged<'u>,
+    OnShellChanging<'u>,
+    OnDressupChanged<'u>,
+    OnBalloonChange<'u>,
+    OnWindowStateRestore,
+    OnWindowStateMinimize,
+    OnFullScreenAppMinimize,
+    OnFullScreenAppRestore,
+}
+
 pub trait RequestType<'u>: Sized {
     const ID: &'static str;
+    #[allow(clippy::result_unit_err)]
     fn from_untyped(untyped: &'u UntypedReq) -> Result<Self, ()>;
+    /// Every field of the request by its name in the struct, for passing the request on to scripts. Fails if a
+    /// field can't be represented as a `FieldValue`.
+    #[allow(clippy::result_unit_err)]
+    fn field_values(&self) -> Result<Vec<(&'static str, FieldValue<'_>)>, ()>;
 }
 
 #[derive(RequestType)]
@@ -78,6 +112,15 @@ pub struct OnBoot<'u> {
 
 pub enum CloseReason { User, System }
 
+impl ToRequestField for CloseReason {
+    fn to_request_field(&self) -> Result<FieldValue<'_>, ()> {
+        match self {
+            CloseReason::User => Ok(FieldValue::Text("user")),
+            CloseReason::System => Ok(FieldValue::Text("system")),
+        }
+    }
+}
+
 impl<'a> FromRequestField<'a> for CloseReason {
     fn from_request_field(field: Option<&'a str>) -> Result<Self, ()> {
         match field {
@@ -105,6 +148,15 @@ pub struct OnGhostChanged<'u> {
 
 pub enum SwitchType { Manual, Automatic }
 
+impl ToRequestField for SwitchType {
+    fn to_request_field(&self) -> Result<FieldValue<'_>, ()> {
+        match self {
+            SwitchType::Manual => Ok(FieldValue::Text("manual")),
+            SwitchType::Automatic => Ok(FieldValue::Text("automatic")),
+        }
+    }
+}
+
 impl<'a> FromRequestField<'a> for SwitchType {
     fn from_request_field(field: Option<&'a str>) -> Result<Self, ()> {
         match field {
@@ -1,7 +1,19 @@
 use std::collections::HashMap;
 use std::str::FromStr;
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::SHIORI_VERSION;
+use crate::request::parse_fields;
+
+lazy_static! {
+    static ref RESPONSE_HEADER: Regex = Regex::new(
+        r"(?m)^SHIORI/[0-9]+\.[0-9]+ (?P<code>[0-9]{3})( [^\r\n]*)?\r?$"
+    ).unwrap();
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum ResponseStatus {
     OK,
     NoContent,
@@ -23,6 +35,7 @@ impl ResponseStatus {
         }.to_string()
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn from_code(code: u32) -> Result<Self, ()> {
         match code {
             200 => Ok(ResponseStatus::OK),
@@ -36,13 +49,11 @@ impl ResponseStatus {
     }
 
     pub fn is_error(&self) -> bool {
-        match self {
-            ResponseStatus::BadRequest | ResponseStatus::InternalServerError => true,
-            _ => false,
-        }
+        matches!(self, ResponseStatus::BadRequest | ResponseStatus::InternalServerError)
     }
 }
 
+#[derive(Default)]
 pub struct ResponseBuilder {
     status: Option<ResponseStatus>,
     fields: HashMap<String, String>,
@@ -63,6 +74,11 @@ impl ResponseBuilder {
         self
     }
 
+    /// Adds an `X-SSTP-PassThru-*` field, which the baseware hands back to the SSTP client that caused the request.
+    pub fn with_passthru(self, name: &str, value: &str) -> Self {
+        self.with_field(&format!("{}{}", crate::sstp::PASSTHRU_PREFIX, name), value)
+    }
+
     pub fn build(self) -> Option<Response> {
         Some(Response {
             status: self.status?,
@@ -71,14 +87,26 @@ impl ResponseBuilder {
     }
 }
 
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Response {
     status: ResponseStatus,
     fields: HashMap<String, String>,
 }
 
 impl Response {
+    /// Parses a response as it is returned to the baseware. The status code must be one that SHIORI/3.0 defines.
+    #[allow(clippy::result_unit_err)]
+    pub fn parse(text: &str) -> Result<Response, ()> {
+        let header = RESPONSE_HEADER.captures(text).ok_or(())?;
+        let code = header.name("code").unwrap().as_str().parse().map_err(|_| ())?;
+        Ok(Response {
+            status: ResponseStatus::from_code(code)?,
+            fields: parse_fields(&text[header.get(0).unwrap().end()..]),
+        })
+    }
+
     pub fn fields_iter(&self) -> impl Iterator<Item=&str> {
-        return self.fields.keys().map(|s| s.as_str())
+        self.fields.keys().map(|s| s.as_str())
     }
 
     pub fn get_field<T: FromStr>(&self, field: &str) -> Option<Result<T, T::Err>> {
@@ -88,6 +116,16 @@ impl Response {
     pub fn status(&self) -> ResponseStatus {
         self.status
     }
+
+    /// Serializes this response as it is returned to the baseware.
+    pub fn to_wire(&self) -> String {
+        let mut response_parts = vec![format!("SHIORI/{} {}", SHIORI_VERSION, self.status.as_str())];
+        for (field, value) in &self.fields {
+            response_parts.push(format!("{}: {}", field, value));
+        }
+        // Apparently these must always end with two CRLFs or the encoding detection fails! Fun!
+        response_parts.join("\r\n") + "\r\n\r\n"
+    }
 }
 
 
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use log::debug;
+
+use super::{Request, Response, SSTP_PORT};
+
+/// Sends SSTP requests over TCP, by default to the baseware on the local machine.
+pub struct Client {
+    address: SocketAddr,
+    timeout: Option<Duration>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Client { address: (Ipv4Addr::LOCALHOST, SSTP_PORT).into(), timeout: Some(Duration::from_secs(5)) }
+    }
+
+    pub fn with_address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Sends `request` and waits for the server's response. The request is always encoded as UTF-8, so its
+    /// `Charset` field is overwritten.
+    pub fn send(&self, request: &Request) -> Result<Response, ClientError> {
+        let request = request.clone().with_field("Charset", "UTF-8");
+        let mut stream = match self.timeout {
+            Some(t) => TcpStream::connect_timeout(&self.address, t)?,
+            None => TcpStream::connect(self.address)?,
+        };
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        let request_str = request.to_wire();
+        debug!("SSTP REQUEST:\n{}", request_str);
+        stream.write_all(request_str.as_bytes())?;
+        stream.flush()?;
+
+        // Servers may either close the connection or leave it open after the terminating blank line.
+        let mut response = Vec::new();
+        let mut buf = [0; 1024];
+        while !response.ends_with(b"\r\n\r\n") {
+            match stream.read(&mut buf)? {
+                0 => break,
+                n => response.extend_from_slice(&buf[..n]),
+            }
+        }
+
+        let response_str = String::from_utf8_lossy(&response);
+        debug!("SSTP RESPONSE:\n{}", response_str);
+        Response::parse(&response_str).map_err(|_| ClientError::BadResponse(response_str.into_owned()))
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    IOError(io::Error),
+    BadResponse(String),
+}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        ClientError::IOError(error)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            ClientError::IOError(e) => write!(f, "An IO error occured while sending an SSTP request. Details:\n{}", e),
+            ClientError::BadResponse(r) => write!(f, "The SSTP server sent a malformed response:\n{}", r),
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::request::parse_fields;
+
+mod client;
+
+pub use self::client::{Client, ClientError};
+
+/// The port SSTP servers (i.e. the baseware) listen on.
+pub const SSTP_PORT: u16 = 9801;
+
+/// The prefix of fields that are carried over unchanged from an SSTP request to the SHIORI request it causes,
+/// and from the SHIORI response back to the SSTP response.
+pub const PASSTHRU_PREFIX: &str = "X-SSTP-PassThru-";
+
+lazy_static! {
+    static ref REQUEST_HEADER: Regex = Regex::new(
+        r"(?m)^(?P<method>SEND|NOTIFY|COMMUNICATE|EXECUTE|GIVE) SSTP/(?P<version>[0-9]+\.[0-9]+)\r?$"
+    ).unwrap();
+
+    static ref RESPONSE_HEADER: Regex = Regex::new(
+        r"(?m)^SSTP/(?P<version>[0-9]+\.[0-9]+) (?P<code>[0-9]{3})( [^\r\n]*)?\r?$"
+    ).unwrap();
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Method {
+    Send,
+    Notify,
+    Communicate,
+    Execute,
+    Give,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Send => "SEND",
+            Method::Notify => "NOTIFY",
+            Method::Communicate => "COMMUNICATE",
+            Method::Execute => "EXECUTE",
+            Method::Give => "GIVE",
+        }
+    }
+
+    /// The newest protocol version defined for this method, used when building requests.
+    pub fn default_version(&self) -> &'static str {
+        match self {
+            Method::Send => "1.4",
+            Method::Notify => "1.1",
+            Method::Communicate => "1.2",
+            Method::Execute => "1.3",
+            Method::Give => "1.1",
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = ();
+    fn from_str(text: &str) -> Result<Method, ()> {
+        match text {
+            "SEND" => Ok(Method::Send),
+            "NOTIFY" => Ok(Method::Notify),
+            "COMMUNICATE" => Ok(Method::Communicate),
+            "EXECUTE" => Ok(Method::Execute),
+            "GIVE" => Ok(Method::Give),
+            _ => Err(())
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Request {
+    method: Method,
+    version: String,
+    fields: HashMap<String, String>,
+}
+
+impl Request {
+    pub fn new(method: Method) -> Self {
+        Request { method, version: method.default_version().to_string(), fields: HashMap::new() }
+    }
+
+    pub fn with_version(mut self, version: &str) -> Self {
+        self.version = version.to_string();
+        self
+    }
+
+    pub fn with_field(mut self, field_name: &str, value: &str) -> Self {
+        self.fields.insert(field_name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_passthru(self, name: &str, value: &str) -> Self {
+        self.with_field(&format!("{}{}", PASSTHRU_PREFIX, name), value)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn parse(text: &str) -> Result<Request, ()> {
+        if let Some(header) = REQUEST_HEADER.captures(text) {
+            return Ok(Request {
+                method: Method::from_str(header.name("method").unwrap().as_str()).unwrap(),
+                version: header.name("version").unwrap().as_str().to_string(),
+                fields: parse_fields(text),
+            })
+        }
+        Err(())
+    }
+
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn fields(&self) -> &HashMap<String, String> {
+        &self.fields
+    }
+
+    pub fn fields_iter(&self) -> impl Iterator<Item=&str> {
+        self.fields.keys().map(|s| s.as_str())
+    }
+
+    pub fn get_field(&self, field: &str) -> Option<&str> {
+        self.fields.get(field).map(|s| s.as_str())
+    }
+
+    pub fn passthru(&self) -> PassThru<'_> {
+        PassThru::from_fields(self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+    }
+
+    /// Serializes this request as it is sent over the network.
+    pub fn to_wire(&self) -> String {
+        write_message(format!("{} SSTP/{}", self.method.as_str(), self.version), &self.fields, &[])
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ResponseStatus {
+    OK,
+    NoContent,
+    Break,
+    BadRequest,
+    NotFound,
+    RequestTimeout,
+    Conflict,
+    Refuse,
+    NotImplemented,
+    ServiceUnavailable,
+    NotLocalIP,
+    InBlackList,
+    Invisible,
+}
+
+impl ResponseStatus {
+    pub fn as_str(&self) -> String {
+        match self {
+            ResponseStatus::OK => "200 OK",
+            ResponseStatus::NoContent => "204 No Content",
+            ResponseStatus::Break => "210 Break",
+            ResponseStatus::BadRequest => "400 Bad Request",
+            ResponseStatus::NotFound => "404 Not Found",
+            ResponseStatus::RequestTimeout => "408 Request Timeout",
+            ResponseStatus::Conflict => "409 Conflict",
+            ResponseStatus::Refuse => "420 Refuse",
+            ResponseStatus::NotImplemented => "501 Not Implemented",
+            ResponseStatus::ServiceUnavailable => "503 Service Unavailable",
+            ResponseStatus::NotLocalIP => "510 Not Local IP",
+            ResponseStatus::InBlackList => "511 In Black List",
+            ResponseStatus::Invisible => "512 Invisible",
+        }.to_string()
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn from_code(code: u32) -> Result<Self, ()> {
+        match code {
+            200 => Ok(ResponseStatus::OK),
+            204 => Ok(ResponseStatus::NoContent),
+            210 => Ok(ResponseStatus::Break),
+            400 => Ok(ResponseStatus::BadRequest),
+            404 => Ok(ResponseStatus::NotFound),
+            408 => Ok(ResponseStatus::RequestTimeout),
+            409 => Ok(ResponseStatus::Conflict),
+            420 => Ok(ResponseStatus::Refuse),
+            501 => Ok(ResponseStatus::NotImplemented),
+            503 => Ok(ResponseStatus::ServiceUnavailable),
+            510 => Ok(ResponseStatus::NotLocalIP),
+            511 => Ok(ResponseStatus::InBlackList),
+            512 => Ok(ResponseStatus::Invisible),
+            _ => Err(())
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        !matches!(self, ResponseStatus::OK | ResponseStatus::NoContent | ResponseStatus::Break)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Response {
+    status: ResponseStatus,
+    version: String,
+    fields: HashMap<String, String>,
+    additional: Vec<String>,
+}
+
+impl Response {
+    pub fn new(status: ResponseStatus, version: &str) -> Self {
+        Response { status, version: version.to_string(), fields: HashMap::new(), additional: Vec::new() }
+    }
+
+    pub fn with_field(mut self, field_name: &str, value: &str) -> Self {
+        self.fields.insert(field_name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_passthru(self, name: &str, value: &str) -> Self {
+        self.with_field(&format!("{}{}", PASSTHRU_PREFIX, name), value)
+    }
+
+    /// Appends a line of additional data, which some `EXECUTE` commands return instead of fields. The first line must
+    /// not look like a field (`Name: Value`), since it would be read back as one.
+    pub fn with_additional(mut self, line: &str) -> Self {
+        self.additional.push(line.to_string());
+        self
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn parse(text: &str) -> Result<Response, ()> {
+        let header = RESPONSE_HEADER.captures(text).ok_or(())?;
+        let code = header.name("code").unwrap().as_str().parse().map_err(|_| ())?;
+        let body = &text[header.get(0).unwrap().end()..];
+        // The fields come first. Additional data starts at the first line that isn't a field, so later lines may
+        // contain ": " too.
+        let lines: Vec<&str> = body.lines().skip(1).map(|l| l.trim_end_matches('\r')).collect();
+        let field_lines = lines.iter().take_while(|l| l.contains(": ")).count();
+        let additional = lines[field_lines..].iter()
+            .filter(|l| !l.is_empty())
+            .map(|l| l.to_string())
+            .collect();
+        Ok(Response {
+            status: ResponseStatus::from_code(code)?,
+            version: header.name("version").unwrap().as_str().to_string(),
+            fields: parse_fields(&lines[..field_lines].join("\n")),
+            additional,
+        })
+    }
+
+    pub fn status(&self) -> ResponseStatus {
+        self.status
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn fields(&self) -> &HashMap<String, String> {
+        &self.fields
+    }
+
+    pub fn fields_iter(&self) -> impl Iterator<Item=&str> {
+        self.fields.keys().map(|s| s.as_str())
+    }
+
+    pub fn get_field(&self, field: &str) -> Option<&str> {
+        self.fields.get(field).map(|s| s.as_str())
+    }
+
+    pub fn additional(&self) -> &[String] {
+        &self.additional
+    }
+
+    pub fn passthru(&self) -> PassThru<'_> {
+        PassThru::from_fields(self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+    }
+
+    /// Serializes this response as it is sent over the network.
+    pub fn to_wire(&self) -> String {
+        write_message(format!("SSTP/{} {}", self.version, self.status.as_str()), &self.fields, &self.additional)
+    }
+}
+
+/// Writes the header line, then `Charset` (which the baseware must see before it can decode anything else),
+/// then the remaining fields in a stable order, then any additional data.
+fn write_message(header: String, fields: &HashMap<String, String>, additional: &[String]) -> String {
+    let mut parts = vec![header];
+    if let Some(charset) = fields.get("Charset") {
+        parts.push(format!("Charset: {}", charset));
+    }
+    let mut names: Vec<&String> = fields.keys().filter(|k| k.as_str() != "Charset").collect();
+    names.sort();
+    parts.extend(names.into_iter().map(|k| format!("{}: {}", k, fields[k])));
+    parts.extend(additional.iter().cloned());
+    parts.join("\r\n") + "\r\n\r\n"
+}
+
+/// The `X-SSTP-PassThru-*` fields of a message, keyed by the part of their name after the prefix.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct PassThru<'a> {
+    fields: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> PassThru<'a> {
+    pub fn from_fields(fields: impl Iterator<Item=(&'a str, &'a str)>) -> Self {
+        PassThru {
+            fields: fields
+                .filter_map(|(k, v)| k.strip_prefix(PASSTHRU_PREFIX).map(|k| (k, v)))
+                .collect()
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.fields.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&'a str, &'a str)> + '_ {
+        self.fields.iter().map(|(k, v)| (*k, *v))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+
+use rust_shiori::{
+    Shiori,
+    context::RequestContext,
+    internals::handle_request,
+    request::{Method, Request},
+    response::{Response, ResponseBuilder, ResponseStatus},
+};
+
+/// Remembers the context of every request it handles.
+#[derive(Default)]
+struct Witness(Vec<Option<RequestContext>>);
+
+impl Shiori for Witness {
+    type LoadError = ();
+    fn load(_: PathBuf) -> Result<Self, ()> { Ok(Witness::default()) }
+    fn respond(&mut self, _: Request) -> Response {
+        self.0.push(RequestContext::current());
+        ResponseBuilder::new().with_status(ResponseStatus::NoContent).build().unwrap()
+    }
+}
+
+#[test]
+fn requests_are_numbered_in_order() {
+    let mut shiori = Witness::default();
+    handle_request(&Request::new(Method::Get).with_field("ID", "OnBoot").to_wire(), &mut shiori);
+    handle_request(&Request::new(Method::Notify).to_wire(), &mut shiori);
+    assert_eq!(RequestContext::current(), None);
+
+    let contexts: Vec<RequestContext> = shiori.0.into_iter().map(Option::unwrap).collect();
+    assert_eq!(contexts[0].event.as_deref(), Some("OnBoot"));
+    assert_eq!(contexts[1].event, None);
+    assert!(contexts[1].id > contexts[0].id);
+    assert_eq!(contexts[0].to_string(), format!("#{} OnBoot", contexts[0].id));
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_shiori::{
+    Shiori,
+    record::{self, Recorder},
+    request::{Method, Request},
+    response::{Response, ResponseBuilder, ResponseStatus},
+};
+
+/// Greets whoever the `ID` field names, with a configurable greeting.
+struct Greeter(&'static str);
+
+impl Shiori for Greeter {
+    type LoadError = ();
+    fn load(_: PathBuf) -> Result<Self, ()> { Ok(Greeter("Hello")) }
+    fn respond(&mut self, request: Request) -> Response {
+        let value = format!("{}, {}!", self.0, request.get_field("ID").unwrap_or("nobody"));
+        ResponseBuilder::new()
+            .with_status(ResponseStatus::OK)
+            .with_field("Charset", "UTF-8")
+            .with_field("Value", &value)
+            .build()
+            .unwrap()
+    }
+}
+
+static NEXT_RECORDING: AtomicUsize = AtomicUsize::new(0);
+
+fn record_session(shiori: &mut Greeter, ids: &[&str]) -> String {
+    let name = format!("rust-shiori-record-{}-{}.rec", std::process::id(), NEXT_RECORDING.fetch_add(1, Ordering::Relaxed));
+    let path = std::env::temp_dir().join(name);
+    let _ = fs::remove_file(&path);
+    let mut recorder = Recorder::open(&path).unwrap();
+    for id in ids {
+        let request = Request::new(Method::Get).with_field("Charset", "UTF-8").with_field("ID", id).to_wire();
+        recorder.record_request(&request).unwrap();
+        recorder.record_response(&shiori.respond(Request::parse(&request).unwrap()).to_wire()).unwrap();
+    }
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    text
+}
+
+#[test]
+fn replay_matches_recording() {
+    let session = record::read_session(&record_session(&mut Greeter("Hello"), &["OnBoot", "OnClose"])).unwrap();
+    assert_eq!(session.len(), 2);
+    assert!(record::replay(&mut Greeter("Hello"), &session).is_empty());
+}
+
+#[test]
+fn replay_reports_differences() {
+    let session = record::read_session(&record_session(&mut Greeter("Hello"), &["OnBoot", "OnClose"])).unwrap();
+    let differences = record::replay(&mut Greeter("Goodbye"), &session);
+    assert_eq!(differences.len(), 2);
+    assert!(differences[0].expected.contains("Hello, OnBoot!"));
+    assert!(differences[0].actual.contains("Goodbye, OnBoot!"));
+}
+
+#[test]
+fn read_session_rejects_garbage() {
+    assert!(record::read_session("GET SHIORI/3.0\r\n\r\n").is_err());
+    assert!(record::read_session("<<< 0\r\nSHIORI/3.0 204 No Content\r\n\r\n").is_err());
+}
+
+#[test]
+fn read_session_accepts_either_line_ending() {
+    let recording = record_session(&mut Greeter("Hello"), &["OnBoot", "OnClose"]);
+    let expected = record::read_session(&recording).unwrap();
+    let session = record::read_session(&recording.replace("\r\n", "\n")).unwrap();
+    assert_eq!(session.len(), 2);
+    for (read, expected) in session.iter().zip(&expected) {
+        assert_eq!(read.request, expected.request);
+        assert_eq!(read.response, expected.response);
+    }
+    assert!(record::replay(&mut Greeter("Hello"), &session).is_empty());
+}
@@ -0,0 +1,74 @@
+use proptest::prelude::*;
+
+use rust_shiori::{
+    request::{Method, Request},
+    response::{Response, ResponseBuilder, ResponseStatus},
+    sstp,
+};
+
+/// Field names and values as they can appear on the wire: names contain no colons, and neither contains a line
+/// break. Names are never the start of a status line, since a field can't be confused with one.
+fn fields() -> impl Strategy<Value = Vec<(String, String)>> {
+    prop::collection::vec(("[A-Za-z0-9_.-][^:\r\n]{0,15}", "[^\r\n]{0,30}"), 0..8)
+}
+
+fn method() -> impl Strategy<Value = Method> {
+    prop_oneof![Just(Method::Get), Just(Method::Notify), Just(Method::Teach)]
+}
+
+fn status() -> impl Strategy<Value = ResponseStatus> {
+    prop::sample::select(vec![200, 204, 311, 312, 400, 500]).prop_map(|c| ResponseStatus::from_code(c).unwrap())
+}
+
+fn sstp_method() -> impl Strategy<Value = sstp::Method> {
+    use rust_shiori::sstp::Method::*;
+    prop::sample::select(vec![Send, Notify, Communicate, Execute, Give])
+}
+
+fn sstp_status() -> impl Strategy<Value = sstp::ResponseStatus> {
+    prop::sample::select(vec![200, 204, 210, 400, 404, 408, 409, 420, 501, 503, 510, 511, 512])
+        .prop_map(|c| sstp::ResponseStatus::from_code(c).unwrap())
+}
+
+proptest! {
+    #[test]
+    fn request_round_trip(method in method(), fields in fields()) {
+        let request = fields.iter().fold(Request::new(method), |r, (k, v)| r.with_field(k, v));
+        prop_assert_eq!(Request::parse(&request.to_wire()), Ok(request));
+    }
+
+    #[test]
+    fn response_round_trip(status in status(), fields in fields()) {
+        let builder = fields.iter().fold(ResponseBuilder::new().with_status(status), |r, (k, v)| r.with_field(k, v));
+        let response = builder.build().unwrap();
+        prop_assert_eq!(Response::parse(&response.to_wire()), Ok(response));
+    }
+
+    #[test]
+    fn sstp_request_round_trip(method in sstp_method(), fields in fields()) {
+        let request = fields.iter().fold(sstp::Request::new(method), |r, (k, v)| r.with_field(k, v));
+        prop_assert_eq!(sstp::Request::parse(&request.to_wire()), Ok(request));
+    }
+
+    #[test]
+    fn sstp_response_round_trip(
+        status in sstp_status(),
+        fields in fields(),
+        first in prop::option::of("[^:\r\n]{1,20}"),
+        rest in prop::collection::vec("[^\r\n]{1,20}", 0..3),
+    ) {
+        // Only the first line of additional data can't look like a field.
+        let additional = first.map_or_else(Vec::new, |first| std::iter::once(first).chain(rest).collect());
+        let response = fields.iter().fold(sstp::Response::new(status, "1.4"), |r, (k, v)| r.with_field(k, v));
+        let response = additional.iter().fold(response, |r, l| r.with_additional(l));
+        prop_assert_eq!(sstp::Response::parse(&response.to_wire()), Ok(response));
+    }
+
+    #[test]
+    fn parse_never_panics(text in "(?s).{0,200}") {
+        let _ = Request::parse(&text).map(|r| r.to_wire());
+        let _ = Response::parse(&text);
+        let _ = sstp::Request::parse(&text);
+        let _ = sstp::Response::parse(&text);
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use rust_shiori::sstp::{Client, Method, Request, Response, ResponseStatus};
+
+/// Accepts a single connection on a loopback port, standing in for the baseware, and answers it with `respond`.
+fn serve_once(respond: impl FnOnce(Request) -> Response + Send + 'static) -> (Client, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = Client::new().with_address(listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 256];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        let request = Request::parse(std::str::from_utf8(&request).unwrap()).unwrap();
+        stream.write_all(respond(request).to_wire().as_bytes()).unwrap();
+    });
+    (client, server)
+}
+
+#[test]
+fn send_round_trip() {
+    let (client, server) = serve_once(|request| {
+        assert!(request.method() == Method::Send);
+        assert_eq!(request.version(), "1.4");
+        assert_eq!(request.get_field("Charset"), Some("UTF-8"));
+        assert_eq!(request.get_field("Script"), Some(r"\0Hello!\e"));
+        assert_eq!(request.passthru().get("Token"), Some("42"));
+        Response::new(ResponseStatus::OK, "1.4").with_field("Script", r"\0Hi!\e").with_passthru("Token", "42")
+    });
+
+    let request = Request::new(Method::Send)
+        .with_field("Sender", "rust-shiori")
+        .with_field("Script", r"\0Hello!\e")
+        .with_passthru("Token", "42");
+    let response = client.send(&request).unwrap();
+    server.join().unwrap();
+
+    assert!(response.status() == ResponseStatus::OK);
+    assert_eq!(response.get_field("Script"), Some(r"\0Hi!\e"));
+    assert_eq!(response.passthru().get("Token"), Some("42"));
+}
+
+#[test]
+fn execute_additional_data() {
+    let (client, server) = serve_once(|request| {
+        assert!(request.method() == Method::Execute);
+        assert_eq!(request.get_field("Command"), Some("GetName"));
+        Response::new(ResponseStatus::OK, "1.3").with_field("Charset", "UTF-8").with_additional("Emily,Teddy")
+    });
+
+    let response = client.send(&Request::new(Method::Execute).with_field("Command", "GetName")).unwrap();
+    server.join().unwrap();
+
+    assert_eq!(response.additional(), &["Emily,Teddy".to_string()]);
+}
+
+#[test]
+fn additional_data_may_look_like_fields_after_its_first_line() {
+    let response = Response::new(ResponseStatus::OK, "1.3")
+        .with_field("Charset", "UTF-8")
+        .with_additional("Emily,Teddy")
+        .with_additional("Note: both are ghosts");
+    let parsed = Response::parse(&response.to_wire()).unwrap();
+    assert_eq!(parsed.additional(), &["Emily,Teddy".to_string(), "Note: both are ghosts".to_string()]);
+    assert_eq!(parsed.get_field("Note"), None);
+    assert_eq!(parsed, response);
+
+    // Additional data may also follow the status line directly, or a blank line after the fields.
+    let wires = [
+        "SSTP/1.3 200 OK\r\nEmily,Teddy\r\nNote: x\r\n\r\n",
+        "SSTP/1.3 200 OK\r\nCharset: UTF-8\r\n\r\nNote: x\r\n",
+    ];
+    for wire in wires {
+        let parsed = Response::parse(wire).unwrap();
+        assert!(parsed.additional().last().unwrap() == "Note: x" && parsed.get_field("Note").is_none(), "{}", wire);
+    }
+}
+
+#[test]
+fn error_status() {
+    let (client, server) = serve_once(|_| Response::new(ResponseStatus::Refuse, "1.4"));
+    let response = client.send(&Request::new(Method::Notify).with_field("Event", "OnTest")).unwrap();
+    server.join().unwrap();
+
+    assert!(response.status().is_error());
+}
+
+#[test]
+fn parse_rejects_garbage() {
+    assert!(Request::parse("GET SHIORI/3.0\r\n\r\n").is_err());
+    assert!(Response::parse("SSTP/1.4 999 Whatever\r\n\r\n").is_err());
+}
@@ -0,0 +1,51 @@
+#![cfg(feature = "typed_request")]
+
+use rust_shiori::request::{FieldValue, Method, Request, typed::RequestKind};
+
+fn request(id: &str, references: &[&str]) -> Request {
+    let request = Request::new(Method::Get).with_field("ID", id).with_field("SecurityLevel", "local");
+    references.iter().enumerate().fold(request, |r, (n, v)| r.with_field(&format!("Reference{}", n), v))
+}
+
+#[test]
+fn known_events_are_typed() {
+    let boot = request("OnBoot", &["master"]);
+    let typed = boot.as_typed();
+    assert!(matches!(typed.kind(), RequestKind::OnBoot(b) if b.shell == "master"));
+    assert_eq!(typed.kind().field_values(), vec![("shell", FieldValue::Text("master"))]);
+    assert_eq!(typed.security_level(), Some("local"));
+
+    let first_boot = request("OnFirstBoot", &["2"]);
+    assert_eq!(first_boot.as_typed().kind().field_values(), vec![("times_uninstalled", FieldValue::Integer(2))]);
+
+    let close = request("OnClose", &["system"]);
+    assert_eq!(close.as_typed().kind().field_values(), vec![("reason", FieldValue::Text("system"))]);
+}
+
+#[test]
+fn optional_fields_may_be_missing() {
+    let changed = request("OnShellChanged", &["default"]);
+    assert_eq!(changed.as_typed().kind().field_values(), vec![
+        ("current_shell", FieldValue::Text("default")),
+        ("ghost", FieldValue::Missing),
+        ("current_shell_path", FieldValue::Missing),
+    ]);
+}
+
+#[test]
+fn malformed_and_unknown_events_are_other() {
+    assert!(matches!(request("OnFirstBoot", &["many"]).as_typed().kind(), RequestKind::Other));
+    assert!(matches!(request("OnClose", &[]).as_typed().kind(), RequestKind::Other));
+    assert!(matches!(request("OnSomethingElse", &["x"]).as_typed().kind(), RequestKind::Other));
+    assert!(request("OnSomethingElse", &[]).as_typed().kind().field_values().is_empty());
+}
+
+#[test]
+fn integers_outside_of_i64_are_left_out() {
+    let largest = request("OnFirstBoot", &["9223372036854775807"]);
+    assert_eq!(largest.as_typed().kind().field_values(), vec![("times_uninstalled", FieldValue::Integer(i64::MAX))]);
+
+    let too_large = request("OnFirstBoot", &["18446744073709551615"]);
+    assert!(matches!(too_large.as_typed().kind(), RequestKind::OnFirstBoot(b) if b.times_uninstalled == usize::MAX));
+    assert!(too_large.as_typed().kind().field_values().is_empty());
+}